@@ -0,0 +1,118 @@
+use crate::simple_engine::*;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+/// Shape of an engine's graph.
+#[derive(Debug, Clone)]
+pub struct GraphStats {
+    /// Number of levels; a term's level is the length of the longest
+    /// upstream chain below it.
+    pub depth: usize,
+    /// Number of terms on each level, the most that could run in parallel.
+    pub widths: Vec<usize>,
+    /// Number of upstream terms -> number of terms with that many.
+    pub fan_in: BTreeMap<usize, usize>,
+    /// Number of downstream terms -> number of terms with that many.
+    pub fan_out: BTreeMap<usize, usize>
+}
+
+impl GraphStats {
+    pub fn max_width(&self) -> usize {
+        self.widths.iter().cloned().max().unwrap_or(0)
+    }
+}
+
+/// Chain of evaluated terms with the largest total evaluation time.
+#[derive(Debug, Clone)]
+pub struct CriticalPath {
+    /// From the most upstream term to the most downstream one.
+    pub terms: Vec<TermIndex>,
+    pub duration: Duration
+}
+
+impl<'a, ErrorType> SimpleEngine<'a, ErrorType>
+where ErrorType: 'a + std::error::Error + 'static
+{
+    pub fn stats(&self) -> GraphStats {
+        let mut levels = HashMap::new();
+        let mut widths = Vec::new();
+        for id in &self.ids {
+            let level = self.level(id, &mut levels);
+            if widths.len() <= level {
+                widths.resize(level + 1, 0);
+            }
+            widths[level] += 1;
+        }
+
+        let mut fan_in = BTreeMap::new();
+        let mut fan_out = BTreeMap::new();
+        let downstream = self.downstream_map();
+        for id in &self.ids {
+            *fan_in.entry(self.upstream_of(id).len()).or_insert(0) += 1;
+            *fan_out.entry(downstream.get(&id.0).map_or(0, Vec::len)).or_insert(0) += 1;
+        }
+
+        GraphStats {
+            depth: widths.len(),
+            widths: widths,
+            fan_in: fan_in,
+            fan_out: fan_out
+        }
+    }
+
+    /// Time spent in the term's own closure, if it has been evaluated. With
+    /// a retry policy this is the total of every attempt, without the
+    /// delays between them.
+    pub fn duration(&self, term: &TermIndex) -> Option<Duration> {
+        self.durations.get(&term.0).cloned()
+    }
+
+    /// Critical path over the terms evaluated so far, using the time each
+    /// spent in its own closure. This is the lower bound on evaluation time
+    /// however many terms run in parallel.
+    ///
+    /// Each term runs at most once, so the path covers every evaluation of
+    /// the engine, not only the last one. A term evaluated by an earlier
+    /// `eval` counts with the time it took then.
+    pub fn critical_path(&self) -> CriticalPath {
+        let mut paths = HashMap::new();
+        let mut best = CriticalPath { terms: Vec::new(), duration: Duration::from_secs(0) };
+        for id in self.ids.iter().filter(|id| self.durations.contains_key(&id.0)) {
+            let path = self.path_to(id, &mut paths);
+            if path.duration > best.duration {
+                best = path;
+            }
+        }
+        best
+    }
+
+    fn level(&self, term: &TermIndex, levels: &mut HashMap<usize, usize>) -> usize {
+        if let Some(level) = levels.get(&term.0) {
+            return *level;
+        }
+        let level = self.upstream_of(term).iter()
+            .map(|upstream| self.level(upstream, levels) + 1)
+            .max()
+            .unwrap_or(0);
+        levels.insert(term.0, level);
+        level
+    }
+
+    // Slowest chain of timed terms ending at `term`.
+    fn path_to(&self, term: &TermIndex, paths: &mut HashMap<usize, CriticalPath>) -> CriticalPath {
+        if let Some(path) = paths.get(&term.0) {
+            return path.clone();
+        }
+
+        let mut path = self.upstream_of(term).iter()
+            .filter(|upstream| self.durations.contains_key(&upstream.0))
+            .map(|upstream| self.path_to(upstream, paths))
+            .max_by_key(|path| path.duration)
+            .unwrap_or(CriticalPath { terms: Vec::new(), duration: Duration::from_secs(0) });
+        path.terms.push(term.clone());
+        path.duration += self.durations[&term.0];
+
+        paths.insert(term.0, path.clone());
+        path
+    }
+}
@@ -0,0 +1,110 @@
+use crate::simple_engine::TermIndex;
+use std::cell::RefCell;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Note,
+    Warning,
+    /// Something went wrong, but the term still produced a value.
+    Error
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub term: TermIndex,
+    /// Element of a map term being computed when the message was emitted.
+    pub element: Option<usize>,
+    pub severity: Severity,
+    pub message: String
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} in term {}", self.severity, self.term.index())?;
+        if let Some(element) = self.element {
+            write!(f, " element {}", element)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+struct Sink {
+    term: TermIndex,
+    element: Option<usize>,
+    diagnostics: Vec<Diagnostic>
+}
+
+thread_local! {
+    static SINK: RefCell<Option<Sink>> = const { RefCell::new(None) };
+}
+
+/// Report a non-fatal message from a term closure. The engine tags it with
+/// the term, and the element for map terms, and collects it. Messages
+/// emitted outside an evaluation are dropped.
+///
+/// The sink is thread-local: only messages emitted on the thread running
+/// the closure are collected. Messages from threads the closure spawns or
+/// hands work to, e.g. a rayon pool, are dropped; send them back and emit
+/// them from the closure's own thread.
+pub fn emit<MessageType: Into<String>>(severity: Severity, message: MessageType) {
+    SINK.with(|sink| {
+        if let Some(sink) = sink.borrow_mut().as_mut() {
+            let diagnostic = Diagnostic {
+                term: sink.term.clone(),
+                element: sink.element,
+                severity: severity,
+                message: message.into()
+            };
+            sink.diagnostics.push(diagnostic);
+        }
+    })
+}
+
+pub fn note<MessageType: Into<String>>(message: MessageType) {
+    emit(Severity::Note, message)
+}
+
+pub fn warning<MessageType: Into<String>>(message: MessageType) {
+    emit(Severity::Warning, message)
+}
+
+pub fn error<MessageType: Into<String>>(message: MessageType) {
+    emit(Severity::Error, message)
+}
+
+// Restores the enclosing sink, also when the closure panics.
+struct SinkGuard(Option<Option<Sink>>);
+
+impl Drop for SinkGuard {
+    fn drop(&mut self) {
+        if let Some(previous) = self.0.take() {
+            SINK.with(|sink| *sink.borrow_mut() = previous);
+        }
+    }
+}
+
+// Runs `func` with a sink for `term`, returning what it emitted.
+pub(crate) fn collect<ResultType, FnType>(term: &TermIndex, func: FnType) -> (ResultType, Vec<Diagnostic>)
+where FnType: FnOnce() -> ResultType {
+    let sink = Sink { term: term.clone(), element: None, diagnostics: Vec::new() };
+    let mut guard = SinkGuard(Some(SINK.with(|current| current.replace(Some(sink)))));
+    let result = func();
+    let previous = guard.0.take().unwrap();
+    let sink = SINK.with(|current| current.replace(previous));
+    (result, sink.map_or(Vec::new(), |sink| sink.diagnostics))
+}
+
+// Runs `func` computing element `index` of the current term.
+pub(crate) fn in_element<ResultType, FnType>(index: usize, func: FnType) -> ResultType
+where FnType: FnOnce() -> ResultType {
+    let set_element = |element| SINK.with(|sink| {
+        if let Some(sink) = sink.borrow_mut().as_mut() {
+            sink.element = element;
+        }
+    });
+    set_element(Some(index));
+    let result = func();
+    set_element(None);
+    result
+}
@@ -1,8 +1,9 @@
 use crate::error::*;
 use crate::generator::*;
-use worm_cell::AtomicWormCellReader;
+use worm_cell::{AtomicWormCell, AtomicWormCellReader};
 use std::ops::Deref;
 use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
 
 #[derive(Clone)]
 pub struct Term<'a, ValueType, ImplType> {
@@ -38,6 +39,58 @@ impl<'a, ElementType, ImplType> ListTerm<'a, ElementType, ImplType> {
     }
 }
 
+/// Term handle usable as upstream before the term it stands in for exists.
+/// Evaluating it before `Engine::bind` is an `EngineError::Unbound`.
+pub struct Placeholder<'a, ValueType, ImplType> {
+    pub(crate) term: Term<'a, ValueType, ImplType>,
+    pub(crate) target: Arc<AtomicWormCell<Term<'a, ValueType, ImplType>>>
+}
+
+impl<'a, ValueType, ImplType> Placeholder<'a, ValueType, ImplType> {
+    pub fn new(term: Term<'a, ValueType, ImplType>, target: Arc<AtomicWormCell<Term<'a, ValueType, ImplType>>>) -> Self {
+        Self {
+            term,
+            target
+        }
+    }
+
+    pub fn term(&self) -> &Term<'a, ValueType, ImplType> {
+        &self.term
+    }
+}
+
+/// Term handle whose value is a borrowed view into the value of another
+/// term, made by `Engine::project`.
+///
+/// A projection can be evaluated with `eval` and `eval_many`, and it can
+/// be added to the upstream of other terms. Its value lives inside the
+/// source term and has no cell of its own. So it is not a `TermValue` and
+/// cannot be turned into a `Term`. That leaves out `invariant`,
+/// `eval_shared`, `eval_tuple`, `switch`, `select`, `recover`, `optional`
+/// and `bind`. Use them on the source term, or copy the part into a term
+/// of its own with `scalar`.
+pub struct Projection<'a, SourceType, ValueType, ImplType> {
+    pub(crate) source: AtomicWormCellReader<SourceType>,
+    pub(crate) func: Arc<dyn Fn(&SourceType) -> &ValueType + 'a>,
+    pub(crate) implementation: ImplType
+}
+
+impl<'a, SourceType, ValueType, ImplType> Projection<'a, SourceType, ValueType, ImplType> {
+    pub fn new(source: AtomicWormCellReader<SourceType>, func: Arc<dyn Fn(&SourceType) -> &ValueType + 'a>, implementation: ImplType) -> Self {
+        Self {
+            source,
+            func,
+            implementation
+        }
+    }
+}
+
+impl<'a, SourceType, ValueType, ImplType: Clone> Clone for Projection<'a, SourceType, ValueType, ImplType> {
+    fn clone(&self) -> Self {
+        Projection::new(self.source.clone(), self.func.clone(), self.implementation.clone())
+    }
+}
+
 pub trait TermLike<'a, ValueType, ImplType> {
     fn try_get(&'a self) -> Result<&'a ValueType, EngineError>;
     fn get_implementation(&'a self) -> &'a ImplType;
@@ -63,6 +116,34 @@ impl<'a, ElementType, ImplType> TermLike<'a, Vec<ElementType>, ImplType> for Lis
     }
 }
 
+impl<'t, 'p, ValueType, ImplType> TermLike<'t, ValueType, ImplType> for Placeholder<'p, ValueType, ImplType> {
+    fn try_get(&'t self) -> Result<&'t ValueType, EngineError> {
+        Ok(self.term.result.try_get()?)
+    }
+
+    fn get_implementation(&'t self) -> &'t ImplType {
+        &self.term.implementation
+    }
+}
+
+impl<'t, 'p, SourceType, ValueType, ImplType> TermLike<'t, ValueType, ImplType> for Projection<'p, SourceType, ValueType, ImplType> {
+    fn try_get(&'t self) -> Result<&'t ValueType, EngineError> {
+        Ok((self.func)(self.source.try_get()?))
+    }
+
+    fn get_implementation(&'t self) -> &'t ImplType {
+        &self.implementation
+    }
+}
+
+impl<'a, SourceType, ValueType, ImplType> Deref for Projection<'a, SourceType, ValueType, ImplType> {
+    type Target = ValueType;
+
+    fn deref(&self) -> &Self::Target {
+        self.try_get().unwrap()
+    }
+}
+
 impl<'a, ElementType, ImplType> ListTerm<'a, ElementType, ImplType> {
     pub fn iter(&'a self) -> std::slice::Iter<'a, ElementType> {
         self.result.try_get().unwrap().iter()
@@ -77,27 +158,240 @@ impl<'a, ValueType, ImplType> Deref for Term<'a, ValueType, ImplType> {
     }
 }
 
+/// Value of a list term that keeps going past failing elements: the
+/// elements that succeeded, in order, and the index and error of each
+/// element that failed.
+#[derive(Debug, Clone)]
+pub struct PartialList<ElementType, ErrorType> {
+    pub elements: Vec<ElementType>,
+    pub errors: Vec<(usize, ErrorType)>
+}
+
+impl<ElementType, ErrorType> PartialList<ElementType, ErrorType> {
+    pub fn new() -> Self {
+        PartialList {
+            elements: Vec::new(),
+            errors: Vec::new()
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+impl<ElementType, ErrorType> Default for PartialList<ElementType, ErrorType> {
+    fn default() -> Self {
+        PartialList::new()
+    }
+}
+
+impl<ElementType, ErrorType> std::iter::FromIterator<Result<ElementType, ErrorType>> for PartialList<ElementType, ErrorType> {
+    fn from_iter<IterType>(iter: IterType) -> Self
+    where IterType: IntoIterator<Item=Result<ElementType, ErrorType>> {
+        let mut partial = PartialList::new();
+        for (index, element) in iter.into_iter().enumerate() {
+            match element {
+                Ok(element) => partial.elements.push(element),
+                Err(e) => partial.errors.push((index, e))
+            }
+        }
+        partial
+    }
+}
+
+/// Value of a term that can be moved out, either by `Engine::take` or by
+/// the one consumer made with `Engine::consume`. Other term values are
+/// write-once and only ever borrowed.
+pub struct Owned<ValueType>(Mutex<Option<ValueType>>);
+
+impl<ValueType> Owned<ValueType> {
+    pub fn new(value: ValueType) -> Self {
+        Owned(Mutex::new(Some(value)))
+    }
+
+    pub fn is_consumed(&self) -> bool {
+        self.lock().is_none()
+    }
+
+    /// Call `func` with the value, unless it was moved out.
+    pub fn with<ResultType, FnType>(&self, func: FnType) -> EngineResult<ResultType>
+    where FnType: FnOnce(&ValueType) -> ResultType {
+        self.lock().as_ref().map(func).ok_or(EngineError::Consumed)
+    }
+
+    pub fn take(&self) -> EngineResult<ValueType> {
+        self.lock().take().ok_or(EngineError::Consumed)
+    }
+
+    // A panic while the lock is held cannot leave the Option half moved.
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<ValueType>> {
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<ValueType> From<ValueType> for Owned<ValueType> {
+    fn from(value: ValueType) -> Self {
+        Owned::new(value)
+    }
+}
+
+/// Owning handle to the value of an evaluated term. It keeps the value alive
+/// on its own, so it can outlive the term handle and the engine and be sent
+/// to other threads.
+pub struct Shared<ValueType>(AtomicWormCellReader<ValueType>);
+
+impl<ValueType> Clone for Shared<ValueType> {
+    fn clone(&self) -> Self {
+        Shared(self.0.clone())
+    }
+}
+
+impl<ValueType> Deref for Shared<ValueType> {
+    type Target = ValueType;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.try_get().unwrap()
+    }
+}
+
+/// Value type of a term handle, for APIs taking handles of several types.
+pub trait TermValue {
+    type ValueType;
+
+    /// Reader of the value, independent of the lifetime of the handle.
+    fn reader(&self) -> AtomicWormCellReader<Self::ValueType>;
+
+    /// Owning handle to the value; fails if the term is not evaluated.
+    fn get_arc(&self) -> EngineResult<Shared<Self::ValueType>> {
+        let reader = self.reader();
+        reader.try_get()?;
+        Ok(Shared(reader))
+    }
+}
+
+impl<'a, ValueType, ImplType> TermValue for Term<'a, ValueType, ImplType> {
+    type ValueType = ValueType;
+
+    fn reader(&self) -> AtomicWormCellReader<ValueType> {
+        self.result.clone()
+    }
+}
+
+impl<'a, ElementType, ImplType> TermValue for ListTerm<'a, ElementType, ImplType> {
+    type ValueType = Vec<ElementType>;
+
+    fn reader(&self) -> AtomicWormCellReader<Vec<ElementType>> {
+        self.result.clone()
+    }
+}
+
+impl<'a, ValueType, ImplType> TermValue for Placeholder<'a, ValueType, ImplType> {
+    type ValueType = ValueType;
+
+    fn reader(&self) -> AtomicWormCellReader<ValueType> {
+        self.term.result.clone()
+    }
+}
+
+/// Tuple of term handles evaluated together by `Engine::eval_tuple`.
+pub trait TermTuple<'t, ImplType, ErrorType>
+where ErrorType: std::error::Error + 'static
+{
+    type Results;
+
+    fn implementations(&self) -> Vec<&'t ImplType>;
+    fn results(self, evaluated: Vec<Result<(), ExpressionError<ErrorType>>>) -> Self::Results;
+}
+
+macro_rules! term_tuple {
+    ($($term:ident),+) => {
+        impl<'t, ImplType, ErrorType, $($term),+> TermTuple<'t, ImplType, ErrorType> for ($(&'t $term,)+)
+        where
+            ErrorType: std::error::Error + 'static,
+            $($term: TermValue + TermLike<'t, $term::ValueType, ImplType>),+
+        {
+            type Results = ($(Result<&'t $term::ValueType, ExpressionError<ErrorType>>,)+);
+
+            #[allow(non_snake_case)]
+            fn implementations(&self) -> Vec<&'t ImplType> {
+                let ($($term,)+) = *self;
+                vec![$($term.get_implementation()),+]
+            }
+
+            #[allow(non_snake_case)]
+            fn results(self, evaluated: Vec<Result<(), ExpressionError<ErrorType>>>) -> Self::Results {
+                let ($($term,)+) = self;
+                let mut evaluated = evaluated.into_iter();
+                ($(evaluated.next().unwrap().and_then(|_| Ok($term.try_get()?)),)+)
+            }
+        }
+    }
+}
+
+term_tuple!(A, B);
+term_tuple!(A, B, C);
+term_tuple!(A, B, C, D);
+term_tuple!(A, B, C, D, E);
+
 pub trait TermSet {
     type TermImpl;
 
     fn new() -> Self;
-    fn add<'a, ValueType, TermType>(self, term: &'a TermType) -> Self
-        where TermType: TermLike<'a, ValueType, Self::TermImpl>;
+
+    /// Add `term`, failing with `EngineError::ForeignTerm` if the set
+    /// belongs to another engine than the term.
+    fn add<'a, ValueType, TermType>(self, term: &'a TermType) -> EngineResult<Self>
+        where TermType: TermLike<'a, ValueType, Self::TermImpl>, Self: Sized;
 }
 
-pub trait Engine<'a> {
+/// Builds terms. Every engine is a builder; subgraph closures get a
+/// builder that can only build terms, not evaluate them.
+pub trait Builder<'a> {
     type ErrorType: std::error::Error;
     type UpstreamSet: TermSet;
     type TermImpl: 'a;
+    /// Builder passed to the closures of `subgraph`.
+    type Subgraph: Builder<'a, ErrorType=Self::ErrorType, UpstreamSet=Self::UpstreamSet, TermImpl=Self::TermImpl>;
 
-    fn eval<'t, ValueType, TermType>(&mut self, term: &'t TermType) -> Result<&'t ValueType, ExpressionError<Self::ErrorType>>
-    where TermType: TermLike<'t, ValueType, Self::TermImpl>,
-    'a: 't {
-        self.eval_impl(&term.get_implementation())?;
-        term.try_get().map_err(|e| ExpressionError::<Self::ErrorType>::Engine(e))
+    /// Name a term for introspection. The name is prefixed with the
+    /// enclosing prefixes, joined by `/`. Builders without introspection
+    /// ignore names.
+    fn name<'t, ValueType, TermType>(&mut self, _term: &'t TermType, _name: &str) -> EngineResult<()>
+    where TermType: TermLike<'t, ValueType, Self::TermImpl> {
+        Ok(())
+    }
+
+    fn term_name(&self, _term: &Self::TermImpl) -> Option<&str> {
+        None
     }
 
-    fn eval_impl(&self, term: &Self::TermImpl) -> Result<(), ExpressionError<Self::ErrorType>>;
+    /// Describe what `term` does, e.g. "loading sales.csv". Errors the term
+    /// produces or passes on from its upstream are wrapped in
+    /// `ExpressionError::Context` with this message.
+    fn context<'t, ValueType, TermType>(&mut self, term: &'t TermType, context: &str) -> EngineResult<()>
+    where TermType: TermLike<'t, ValueType, Self::TermImpl>;
+
+    /// Like `context`, but the message is only built when an error occurs.
+    fn with_context<'t, ValueType, TermType, FnType>(&mut self, term: &'t TermType, context: FnType) -> EngineResult<()>
+    where
+        TermType: TermLike<'t, ValueType, Self::TermImpl>,
+        FnType: Fn() -> String + 'a;
+
+    /// Check `check` on the value of `term` right after it is computed,
+    /// failing the term with `EngineError::InvariantViolated` naming
+    /// `invariant` if it returns false. A term that already has its value,
+    /// like a constant, is checked right away and the violation returned.
+    fn invariant<'t, TermType, FnType>(&mut self, term: &'t TermType, invariant: &str, check: FnType) -> EngineResult<()>
+    where
+        TermType: TermValue + TermLike<'t, TermType::ValueType, Self::TermImpl>,
+        TermType::ValueType: 'a,
+        FnType: Fn(&TermType::ValueType) -> bool + 'a;
+
+    /// Prefix applied to every name given until the matching `pop_prefix`.
+    fn push_prefix(&mut self, _prefix: &str) {}
+
+    fn pop_prefix(&mut self) {}
 
     fn scalar<'t, ValueType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> Term<'t, ValueType, Self::TermImpl>
     where
@@ -105,23 +399,45 @@ pub trait Engine<'a> {
         FnType: Fn() -> ValueType + 'a,
         'a: 't;
         
-    fn scalar_err<ValueType, ErrorType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> Term<ValueType, Self::TermImpl>
+    fn scalar_err<'t, ValueType, ErrorType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> Term<'t, ValueType, Self::TermImpl>
     where
         ValueType: 'a,
         FnType: Fn() -> Result<ValueType, ErrorType> + 'a,
-        Self::ErrorType: From<ErrorType>;
+        Self::ErrorType: From<ErrorType>,
+        'a: 't;
+
+    /// Term for each output of `func`, which runs once for both. A term
+    /// depending on one output does not depend on the other. The outputs
+    /// share an upstream term of type `()` that runs `func`. It has no
+    /// name and is listed by `terms` and `stats` like any other term.
+    fn scalar2<'t, FirstType, SecondType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> (Term<'t, FirstType, Self::TermImpl>, Term<'t, SecondType, Self::TermImpl>)
+    where
+        FirstType: 'a,
+        SecondType: 'a,
+        FnType: Fn() -> (FirstType, SecondType) + 'a,
+        'a: 't;
 
-    fn list<ElementType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> ListTerm<ElementType, Self::TermImpl>
+    fn scalar3<'t, FirstType, SecondType, ThirdType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> (Term<'t, FirstType, Self::TermImpl>, Term<'t, SecondType, Self::TermImpl>, Term<'t, ThirdType, Self::TermImpl>)
+    where
+        FirstType: 'a,
+        SecondType: 'a,
+        ThirdType: 'a,
+        FnType: Fn() -> (FirstType, SecondType, ThirdType) + 'a,
+        'a: 't;
+
+    fn list<'t, ElementType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> ListTerm<'t, ElementType, Self::TermImpl>
     where
         ElementType: 'a,
-        FnType: Fn() -> Vec<ElementType> + 'a;
+        FnType: Fn() -> Vec<ElementType> + 'a,
+        'a: 't;
 
 
-    fn list_err<ElementType, ErrorType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> ListTerm<ElementType, Self::TermImpl>
+    fn list_err<'t, ElementType, ErrorType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> ListTerm<'t, ElementType, Self::TermImpl>
     where
         ElementType: 'a,
         FnType: Fn() -> Result<Vec<ElementType>, ErrorType> + 'a,
-        Self::ErrorType: From<ErrorType>;
+        Self::ErrorType: From<ErrorType>,
+        'a: 't;
 
     fn generator<'t, ElementType, GeneratorType>(&mut self, generator: GeneratorType, upstream: Self::UpstreamSet) -> ListTerm<'t, ElementType, Self::TermImpl>
     where
@@ -129,11 +445,48 @@ pub trait Engine<'a> {
         GeneratorType: Generator<Item=ElementType> + 'a,
         'a: 't;
 
-    fn generator_err<ElementType, ErrorType, GeneratorType>(&mut self, generator: GeneratorType, upstream: Self::UpstreamSet) -> ListTerm<ElementType, Self::TermImpl>
+    fn generator_err<'t, ElementType, ErrorType, GeneratorType>(&mut self, generator: GeneratorType, upstream: Self::UpstreamSet) -> ListTerm<'t, ElementType, Self::TermImpl>
     where
         ElementType: 'a,
         GeneratorType: Generator<Item=Result<ElementType, ErrorType>> + 'a,
-        Self::ErrorType: From<ErrorType>;
+        Self::ErrorType: From<ErrorType>,
+        'a: 't;
+
+    /// Like `generator_err`, but a failing element does not fail the term;
+    /// it is reported with its index in the resulting `PartialList`.
+    fn generator_partial<'t, ElementType, ErrorType, GeneratorType>(&mut self, generator: GeneratorType, upstream: Self::UpstreamSet) -> Term<'t, PartialList<ElementType, ErrorType>, Self::TermImpl>
+    where
+        ElementType: 'a,
+        ErrorType: 'a,
+        GeneratorType: Generator<Item=Result<ElementType, ErrorType>> + 'a,
+        'a: 't;
+
+    /// Term holding `value`, evaluated from the start.
+    fn constant<'t, ValueType>(&mut self, value: ValueType) -> Term<'t, ValueType, Self::TermImpl>
+    where
+        ValueType: 'a,
+        'a: 't;
+
+    /// Like `scalar`, but the closure runs at most once and may move what
+    /// it captures into its result.
+    fn scalar_once<'t, ValueType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> Term<'t, ValueType, Self::TermImpl>
+    where
+        ValueType: 'a,
+        FnType: FnOnce() -> ValueType + 'a,
+        'a: 't;
+
+    fn list_once<'t, ElementType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> ListTerm<'t, ElementType, Self::TermImpl>
+    where
+        ElementType: 'a,
+        FnType: FnOnce() -> Vec<ElementType> + 'a,
+        'a: 't;
+
+    /// List term collecting the elements of `elements`, consuming it.
+    fn generator_once<'t, ElementType, IterType>(&mut self, elements: IterType, upstream: Self::UpstreamSet) -> ListTerm<'t, ElementType, Self::TermImpl>
+    where
+        ElementType: 'a,
+        IterType: IntoIterator<Item=ElementType> + 'a,
+        'a: 't;
 
     fn map<'t, SetupType, ElementType, GeneratorType, MapFnType>(&mut self, generator: GeneratorType, map_fn: MapFnType, upstream: Self::UpstreamSet) -> ListTerm<'t, ElementType, Self::TermImpl>
     where
@@ -142,14 +495,156 @@ pub trait Engine<'a> {
         MapFnType: Fn(SetupType) -> ElementType + 'a,
         'a: 't;
 
-    fn map_err<SetupType, ElementType, ErrorType, GeneratorType, MapFnType>(&mut self, generator: GeneratorType, map_fn: MapFnType, upstream: Self::UpstreamSet) -> ListTerm<ElementType, Self::TermImpl>
+    fn map_err<'t, SetupType, ElementType, ErrorType, GeneratorType, MapFnType>(&mut self, generator: GeneratorType, map_fn: MapFnType, upstream: Self::UpstreamSet) -> ListTerm<'t, ElementType, Self::TermImpl>
+    where
+        ElementType: 'a,
+        GeneratorType: Generator<Item=Result<SetupType, ErrorType>> + 'a,
+        MapFnType: Fn(SetupType) -> Result<ElementType, ErrorType> + 'a,
+        Self::ErrorType: From<ErrorType>,
+        'a: 't;
+
+    /// Like `map_err`, but a failing element does not fail the term; it is
+    /// reported with its index in the resulting `PartialList`.
+    fn map_partial<'t, SetupType, ElementType, ErrorType, GeneratorType, MapFnType>(&mut self, generator: GeneratorType, map_fn: MapFnType, upstream: Self::UpstreamSet) -> Term<'t, PartialList<ElementType, ErrorType>, Self::TermImpl>
     where
         ElementType: 'a,
+        ErrorType: 'a,
         GeneratorType: Generator<Item=Result<SetupType, ErrorType>> + 'a,
         MapFnType: Fn(SetupType) -> Result<ElementType, ErrorType> + 'a,
-        Self::ErrorType: From<ErrorType>;
+        'a: 't;
+
+    /// Term taking the value of `term`, or the value `func` makes from its
+    /// error if `term` fails (see `ExpressionError::is_failure`). The
+    /// failure of `term` itself is still recorded; engine errors are passed
+    /// on.
+    fn recover<'t, ValueType, FnType>(&mut self, term: &Term<'t, ValueType, Self::TermImpl>, func: FnType) -> Term<'t, ValueType, Self::TermImpl>
+    where
+        ValueType: Clone + 'a,
+        FnType: Fn(&ExpressionError<Self::ErrorType>) -> ValueType + 'a,
+        'a: 't;
+
+    /// Term computed by `func` from the value of `term`, moved out of it
+    /// instead of borrowed. The consumer must be the only term reading the
+    /// value. Making a consumer for a value that already has a downstream
+    /// term fails with `EngineError::Consumed`. So does adding the value
+    /// to an upstream set once it has a consumer.
+    fn consume<'t, ValueType, ResultType, FnType>(&mut self, term: &Term<'t, Owned<ValueType>, Self::TermImpl>, func: FnType) -> EngineResult<Term<'t, ResultType, Self::TermImpl>>
+    where
+        ValueType: 'a,
+        ResultType: 'a,
+        FnType: FnOnce(ValueType) -> ResultType + 'a,
+        'a: 't;
+
+    /// Term taking `Some` value of `term`, or `None` if `term` fails.
+    fn optional<'t, ValueType>(&mut self, term: &Term<'t, ValueType, Self::TermImpl>) -> Term<'t, Option<ValueType>, Self::TermImpl>
+    where
+        ValueType: Clone + 'a,
+        'a: 't;
+
+    /// Term taking the value of the branch whose key matches `key`, or of
+    /// `default` if none does. Only the key and the chosen branch are
+    /// evaluated.
+    fn switch<'t, KeyType, ValueType>(&mut self, key: &Term<'t, KeyType, Self::TermImpl>, cases: Vec<(KeyType, &Term<'t, ValueType, Self::TermImpl>)>, default: &Term<'t, ValueType, Self::TermImpl>) -> Term<'t, ValueType, Self::TermImpl>
+    where
+        KeyType: PartialEq + 'a,
+        ValueType: Clone + 'a,
+        'a: 't;
+
+    /// Lazy `if`: only the branch picked by `condition` is evaluated.
+    fn select<'t, ValueType>(&mut self, condition: &Term<'t, bool, Self::TermImpl>, then_term: &Term<'t, ValueType, Self::TermImpl>, else_term: &Term<'t, ValueType, Self::TermImpl>) -> Term<'t, ValueType, Self::TermImpl>
+    where
+        ValueType: Clone + 'a,
+        'a: 't {
+        self.switch(condition, vec![(true, then_term)], else_term)
+    }
+
+    /// Term whose subgraph is built at evaluation time. Once `upstream` is
+    /// evaluated, `func` is called once with an empty builder to build
+    /// further terms; those are spliced into this engine and the term it
+    /// returns is evaluated as part of the same `eval`. Names given inside
+    /// `func` get the prefixes in effect when the subgraph term was made.
+    fn subgraph<'t, ValueType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> Term<'t, ValueType, Self::TermImpl>
+    where
+        ValueType: Clone + 'a,
+        FnType: FnOnce(&mut Self::Subgraph) -> Term<'a, ValueType, Self::TermImpl> + 'a,
+        'a: 't;
+
+    /// Term viewing the part of the value of `term` selected by `func`,
+    /// without copying it. Terms depending on the projection depend on
+    /// `term` through it.
+    fn project<'t, TermType, ValueType, FnType>(&mut self, term: &'t TermType, func: FnType) -> Projection<'a, TermType::ValueType, ValueType, Self::TermImpl>
+    where
+        TermType: TermValue + TermLike<'t, TermType::ValueType, Self::TermImpl>,
+        TermType::ValueType: 'a,
+        ValueType: 'a,
+        FnType: Fn(&TermType::ValueType) -> &ValueType + 'a;
+
+    /// Term standing in for one built later, see `bind`.
+    fn placeholder<ValueType>(&mut self) -> Placeholder<'a, ValueType, Self::TermImpl>
+    where ValueType: Clone + 'a;
+
+    /// Make `placeholder` take the value of `term`. A placeholder can only
+    /// be bound once, and not to a term depending on it, which fails with
+    /// `EngineError::Cycle`.
+    fn bind<'t, ValueType>(&mut self, placeholder: &Placeholder<'a, ValueType, Self::TermImpl>, term: &Term<'t, ValueType, Self::TermImpl>) -> Result<(), EngineError>;
 
     fn upstream(&self) -> Self::UpstreamSet {
         Self::UpstreamSet::new()
     }
 }
+
+pub trait Engine<'a>: Builder<'a> {
+    fn eval<'t, ValueType, TermType>(&mut self, term: &'t TermType) -> Result<&'t ValueType, ExpressionError<Self::ErrorType>>
+    where TermType: TermLike<'t, ValueType, Self::TermImpl>,
+    'a: 't {
+        self.eval_impl(&term.get_implementation())?;
+        term.try_get().map_err(|e| ExpressionError::<Self::ErrorType>::Engine(e))
+    }
+
+    fn eval_impl(&mut self, term: &Self::TermImpl) -> Result<(), ExpressionError<Self::ErrorType>>;
+
+    /// Like `eval`, but returns an owning handle to the value.
+    fn eval_shared<'t, TermType>(&mut self, term: &'t TermType) -> Result<Shared<TermType::ValueType>, ExpressionError<Self::ErrorType>>
+    where TermType: TermValue + TermLike<'t, TermType::ValueType, Self::TermImpl>,
+    'a: 't {
+        self.eval_impl(term.get_implementation())?;
+        Ok(term.get_arc()?)
+    }
+
+    /// Evaluate `term` and move its value out. Later reads of the value get
+    /// `EngineError::Consumed`, and so does taking a value that has a
+    /// consumer.
+    fn take<'t, ValueType, TermType>(&mut self, term: &'t TermType) -> Result<ValueType, ExpressionError<Self::ErrorType>>
+    where
+        TermType: TermLike<'t, Owned<ValueType>, Self::TermImpl>,
+        ValueType: 't,
+        'a: 't {
+        Ok(self.eval(term)?.take()?)
+    }
+
+    /// Evaluate several terms, returning one result per term. A failing
+    /// term does not stop the others from being evaluated.
+    fn eval_many<'t, ValueType, TermType>(&mut self, terms: &[&'t TermType]) -> Vec<Result<&'t ValueType, ExpressionError<Self::ErrorType>>>
+    where TermType: TermLike<'t, ValueType, Self::TermImpl>,
+    'a: 't {
+        let implementations: Vec<_> = terms.iter().map(|term| term.get_implementation()).collect();
+        self.eval_impl_many(&implementations).into_iter()
+            .zip(terms)
+            .map(|(result, term)| result.and_then(|_| Ok(term.try_get()?)))
+            .collect()
+    }
+
+    /// Like `eval_many`, for a tuple of terms of different types.
+    fn eval_tuple<'t, TupleType>(&mut self, terms: TupleType) -> TupleType::Results
+    where TupleType: TermTuple<'t, Self::TermImpl, Self::ErrorType>,
+    'a: 't {
+        let results = self.eval_impl_many(&terms.implementations());
+        terms.results(results)
+    }
+
+    /// Evaluate the union of the subgraphs of `terms`. Engines that schedule
+    /// work can override this to plan the shared upstream once.
+    fn eval_impl_many(&mut self, terms: &[&Self::TermImpl]) -> Vec<Result<(), ExpressionError<Self::ErrorType>>> {
+        terms.iter().map(|term| self.eval_impl(term)).collect()
+    }
+}
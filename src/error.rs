@@ -1,11 +1,21 @@
 use std::error;
 use std::fmt;
+use std::sync::Arc;
 use worm_cell::error::WormCellError;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum EngineError {
     GetNotCalculated,
-    DoubleCalc
+    DoubleCalc,
+    Unbound,
+    TermRemoved,
+    ForeignTerm,
+    /// `term` is the index of the term within its engine.
+    Panicked { term: usize, message: String },
+    InvariantViolated { term: usize, invariant: String },
+    OnceRerun,
+    Consumed,
+    Cycle
 }
 
 impl error::Error for EngineError {}
@@ -14,7 +24,15 @@ impl fmt::Display for EngineError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             EngineError::GetNotCalculated => write!(f, "Tried to get() a result that has not been calculated"),
-            EngineError::DoubleCalc => write!(f, "Tried to calculate a result that has already been calculated")
+            EngineError::DoubleCalc => write!(f, "Tried to calculate a result that has already been calculated"),
+            EngineError::Unbound => write!(f, "Tried to evaluate a placeholder that has not been bound"),
+            EngineError::TermRemoved => write!(f, "Tried to use a term that has been removed from the engine"),
+            EngineError::ForeignTerm => write!(f, "Tried to use a term that belongs to another engine"),
+            EngineError::Panicked { term, message } => write!(f, "Term {} panicked: {}", term, message),
+            EngineError::InvariantViolated { term, invariant } => write!(f, "Term {} violates invariant: {}", term, invariant),
+            EngineError::OnceRerun => write!(f, "Tried to run a closure that can only run once again"),
+            EngineError::Consumed => write!(f, "Tried to read a value that has been moved out of its term"),
+            EngineError::Cycle => write!(f, "Tried to bind a placeholder to a term that depends on it")
         }
     }
 }
@@ -23,14 +41,26 @@ impl fmt::Display for EngineError {
 pub enum ExpressionError<EvalError>
 where EvalError: error::Error + 'static {
     Engine(EngineError),
-    Eval(EvalError)
+    Eval(EvalError),
+    /// Element `index` of a list term failed. Elements are evaluated in
+    /// order, so this is the lowest failing index.
+    Element { index: usize, error: EvalError },
+    /// The term with index `term` failed with `error`. The engine keeps
+    /// the error until `retry`, and every evaluation reaching the term gets
+    /// it, starting with the one that ran the term.
+    Failed { term: usize, error: Arc<ExpressionError<EvalError>> },
+    /// `error` happened while doing what `context` describes.
+    Context { context: String, error: Box<ExpressionError<EvalError>> }
 }
 
 impl<EvalError: error::Error + 'static > error::Error for ExpressionError<EvalError> {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
             ExpressionError::<EvalError>::Engine(engine) => Some(engine),
-            ExpressionError::<EvalError>::Eval(eval) => Some(eval)
+            ExpressionError::<EvalError>::Eval(eval) => Some(eval),
+            ExpressionError::<EvalError>::Element { error, .. } => Some(error),
+            ExpressionError::<EvalError>::Failed { error, .. } => Some(&**error),
+            ExpressionError::<EvalError>::Context { error, .. } => Some(&**error)
         }
     }
 }
@@ -39,7 +69,51 @@ impl<EvalError: error::Error + 'static > fmt::Display for ExpressionError<EvalEr
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Engine(engine) => write!(f, "Engine error: {}", engine),
-            Self::Eval(eval) => write!(f, "Eval error: {}", eval)
+            Self::Eval(eval) => write!(f, "Eval error: {}", eval),
+            Self::Element { index, error } => write!(f, "Eval error in element {}: {}", index, error),
+            Self::Failed { term, error } => write!(f, "Term {} failed: {}", term, error),
+            Self::Context { context, error } => {
+                write!(f, "{}: ", context)?;
+                error.fmt_in_context(f)
+            }
+        }
+    }
+}
+
+impl<EvalError: error::Error + 'static> ExpressionError<EvalError> {
+    // Displays the error after a context, without the kind prefixes, so a
+    // chain reads "building report: loading sales.csv: invalid digit".
+    fn fmt_in_context(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Engine(engine) => write!(f, "{}", engine),
+            Self::Eval(eval) => write!(f, "{}", eval),
+            Self::Element { index, error } => write!(f, "element {}: {}", index, error),
+            Self::Failed { error, .. } => error.fmt_in_context(f),
+            Self::Context { .. } => write!(f, "{}", self)
+        }
+    }
+}
+
+impl<EvalError: error::Error + 'static> ExpressionError<EvalError> {
+    /// Whether a term failed: its closure returned an error or panicked, or
+    /// its value violates an invariant. Other engine errors come from
+    /// misusing the engine, e.g. with a removed term or an unbound
+    /// placeholder.
+    pub fn is_failure(&self) -> bool {
+        match self {
+            Self::Eval(_) | Self::Element { .. } | Self::Failed { .. } => true,
+            Self::Engine(EngineError::Panicked { .. }) | Self::Engine(EngineError::InvariantViolated { .. }) => true,
+            Self::Engine(_) => false,
+            Self::Context { error, .. } => error.is_failure()
+        }
+    }
+
+    /// The error as the failing term returned it: the error a `Failed`
+    /// holds, any other error itself.
+    pub fn original(&self) -> &Self {
+        match self {
+            Self::Failed { error, .. } => error.original(),
+            _ => self
         }
     }
 }
@@ -56,6 +130,62 @@ impl<EvalError: error::Error + 'static> From<WormCellError> for ExpressionError<
     }
 }
 
+impl ExpressionError<BoxedError> {
+    /// The error a term failed with, if it has type `T`. Looks through
+    /// contexts, engine errors and cached failures.
+    pub fn downcast_ref<T: error::Error + 'static>(&self) -> Option<&T> {
+        match self {
+            Self::Engine(engine) => (engine as &(dyn error::Error + 'static)).downcast_ref(),
+            Self::Eval(eval) => eval.get_ref().downcast_ref(),
+            Self::Element { error, .. } => error.get_ref().downcast_ref(),
+            Self::Failed { error, .. } => error.downcast_ref(),
+            Self::Context { error, .. } => error.downcast_ref()
+        }
+    }
+
+    pub fn is<T: error::Error + 'static>(&self) -> bool {
+        self.downcast_ref::<T>().is_some()
+    }
+}
+
+/// Error type of an engine whose terms fail with unrelated error types.
+/// Closures return `Box<dyn Error + Send + Sync>`, so `?` converts any
+/// error, and `ExpressionError::downcast_ref` recovers the original.
+#[derive(Debug)]
+pub struct BoxedError(Box<dyn error::Error + Send + Sync>);
+
+impl BoxedError {
+    pub fn new<E: error::Error + Send + Sync + 'static>(error: E) -> Self {
+        BoxedError(Box::new(error))
+    }
+
+    pub fn get_ref(&self) -> &(dyn error::Error + Send + Sync + 'static) {
+        &*self.0
+    }
+
+    pub fn into_inner(self) -> Box<dyn error::Error + Send + Sync> {
+        self.0
+    }
+}
+
+impl error::Error for BoxedError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+impl fmt::Display for BoxedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<Box<dyn error::Error + Send + Sync>> for BoxedError {
+    fn from(error: Box<dyn error::Error + Send + Sync>) -> BoxedError {
+        BoxedError(error)
+    }
+}
+
 #[derive(Debug)]
 pub enum OpError {
     NeverError
@@ -0,0 +1,212 @@
+use crate::simple_engine::*;
+use crate::error::*;
+use std::collections::{BTreeSet, HashMap};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TermState {
+    Pending,
+    Evaluated,
+    Failed,
+    /// Evaluated, but the value was moved out by `take` or a consumer.
+    Consumed
+}
+
+/// Read-only description of one term of an engine.
+#[derive(Debug, Clone)]
+pub struct TermInfo {
+    pub index: TermIndex,
+    pub name: Option<String>,
+    /// Includes the branch chosen by a lazy term once it is known.
+    pub upstream: Vec<TermIndex>,
+    pub downstream: Vec<TermIndex>,
+    pub state: TermState,
+    pub value_type: &'static str,
+    /// Closure runs of the last evaluation, retries included; 0 if the
+    /// closure never ran.
+    pub attempts: usize
+}
+
+/// What an `eval` would run, computed without calling any closure. Branches
+/// of lazy terms whose condition is not evaluated yet, and terms a subgraph
+/// has yet to build, cannot be known in advance and are not included.
+/// Neither are terms `eval` fails without running: terms with a cached
+/// failure or a consumed value, and the terms depending on them.
+#[derive(Debug, Clone)]
+pub struct Plan {
+    /// Pending terms in the order `eval` runs them.
+    pub order: Vec<TermIndex>,
+    /// Pending terms grouped into waves; every term only depends on terms
+    /// of earlier waves, so the terms of a wave can run in parallel.
+    pub levels: Vec<Vec<TermIndex>>
+}
+
+// Where `plan` puts a term.
+#[derive(Clone, Copy)]
+enum Wave {
+    Evaluated,
+    // Fails without running, see Plan.
+    Failed,
+    At(usize)
+}
+
+impl<'a, ErrorType> SimpleEngine<'a, ErrorType>
+where ErrorType: 'a + std::error::Error + 'static
+{
+    /// Describe every term, in index order.
+    pub fn terms(&self) -> Vec<TermInfo> {
+        let mut downstream = self.downstream_map();
+        self.ids.iter()
+            .map(|id| self.describe(id, downstream.remove(&id.0).unwrap_or_default()))
+            .collect()
+    }
+
+    pub fn term_info(&self, term: &TermIndex) -> EngineResult<TermInfo> {
+        self.expr(term)?;
+        let downstream = self.ids.iter()
+            .filter(|other| self.upstream_of(other).contains(term))
+            .cloned()
+            .collect();
+        Ok(self.describe(term, downstream))
+    }
+
+    fn describe(&self, term: &TermIndex, downstream: Vec<TermIndex>) -> TermInfo {
+        let expr = &self.terms[self.slots[&term.0]];
+        let state = if self.failed.contains(&term.0) {
+            TermState::Failed
+        } else if self.consumed.contains(&term.0) {
+            TermState::Consumed
+        } else if expr.evaluated() {
+            TermState::Evaluated
+        } else {
+            TermState::Pending
+        };
+
+        TermInfo {
+            index: term.clone(),
+            name: self.names.get(&term.0).cloned(),
+            upstream: self.upstream_of(term),
+            downstream: downstream,
+            state: state,
+            value_type: expr.value_type(),
+            attempts: self.attempts.get(&term.0).cloned().unwrap_or(0)
+        }
+    }
+
+    pub fn plan(&self, term: &TermIndex) -> EngineResult<Plan> {
+        let mut order = Vec::new();
+        let mut waves_of = HashMap::new();
+        self.plan_impl(term, &mut order, &mut waves_of)?;
+
+        let mut waves = Vec::new();
+        for index in &order {
+            let level = match waves_of[&index.0] {
+                Wave::At(level) => level,
+                _ => unreachable!("only pending terms are planned")
+            };
+            if waves.len() <= level {
+                waves.resize(level + 1, Vec::new());
+            }
+            waves[level].push(index.clone());
+        }
+
+        Ok(Plan {
+            order: order,
+            levels: waves
+        })
+    }
+
+    fn plan_impl(&self, term: &TermIndex, order: &mut Vec<TermIndex>, waves: &mut HashMap<usize, Wave>) -> EngineResult<Wave> {
+        if let Some(wave) = waves.get(&term.0) {
+            return Ok(*wave);
+        }
+        let expr = self.expr(term)?;
+        let wave = if self.errors.contains_key(&term.0) || self.check_consumed(term).is_err() {
+            Wave::Failed
+        } else if expr.evaluated() {
+            Wave::Evaluated
+        } else {
+            // Like eval_impl, stop at the first failing upstream term; the
+            // failure of a source is recovered from.
+            let mut level = 0;
+            let mut failed = false;
+            for upstream in expr.upstream().0.iter().chain(&expr.branch()) {
+                match self.plan_impl(upstream, order, waves)? {
+                    Wave::Evaluated => (),
+                    Wave::Failed => {
+                        failed = true;
+                        break;
+                    },
+                    Wave::At(upstream_level) => level = level.max(upstream_level + 1)
+                }
+            }
+            if let (false, Some(source)) = (failed, expr.source()) {
+                if let Wave::At(source_level) = self.plan_impl(&source, order, waves)? {
+                    level = level.max(source_level + 1);
+                }
+            }
+            if failed {
+                Wave::Failed
+            } else {
+                order.push(term.clone());
+                Wave::At(level)
+            }
+        };
+        waves.insert(term.0, wave);
+        Ok(wave)
+    }
+
+    /// Error of the last failed evaluation of `term`'s own closure, kept
+    /// until `retry`, including failures a recovering term turned into a
+    /// value.
+    pub fn term_error(&self, term: &TermIndex) -> Option<&ExpressionError<ErrorType>> {
+        self.errors.get(&term.0).map(|error| &**error)
+    }
+
+    /// Every term `term` depends on, directly or not, in index order.
+    pub fn ancestors(&self, term: &TermIndex) -> EngineResult<Vec<TermIndex>> {
+        self.expr(term)?;
+        Ok(self.closure(term, |index| self.upstream_of(index)))
+    }
+
+    /// Every term depending on `term`, directly or not, in index order.
+    pub fn descendants(&self, term: &TermIndex) -> EngineResult<Vec<TermIndex>> {
+        self.expr(term)?;
+        let downstream = self.downstream_map();
+        Ok(self.closure(term, |index| downstream.get(&index.0).cloned().unwrap_or_default()))
+    }
+
+    // Empty for a removed term.
+    pub(crate) fn upstream_of(&self, term: &TermIndex) -> Vec<TermIndex> {
+        match self.expr(term) {
+            Ok(expr) => {
+                let mut upstream = expr.upstream().0.clone();
+                upstream.extend(expr.branch());
+                upstream.extend(expr.source());
+                upstream
+            },
+            Err(_) => Vec::new()
+        }
+    }
+
+    pub(crate) fn downstream_map(&self) -> HashMap<usize, Vec<TermIndex>> {
+        let mut downstream = HashMap::new();
+        for id in &self.ids {
+            for upstream in self.upstream_of(id) {
+                downstream.entry(upstream.0).or_insert_with(Vec::new).push(id.clone());
+            }
+        }
+        downstream
+    }
+
+    fn closure<NextFn>(&self, term: &TermIndex, next: NextFn) -> Vec<TermIndex>
+    where NextFn: Fn(&TermIndex) -> Vec<TermIndex> {
+        let mut found = BTreeSet::new();
+        let mut pending = next(term);
+        while let Some(index) = pending.pop() {
+            if found.insert(index.clone()) {
+                pending.extend(next(&index));
+            }
+        }
+        found.into_iter().collect()
+    }
+}
@@ -8,9 +8,14 @@ pub mod engine;
 //pub mod expression;
 //pub mod list;
 pub mod simple_engine;
+pub mod introspection;
+pub mod analysis;
 //pub mod ops;
 pub mod generator;
 pub mod generator_func;
+pub mod template;
+pub mod retry;
+pub mod diagnostics;
 mod test_simple_engine;
 
 pub use crate::error::*;
@@ -19,3 +24,5 @@ pub use crate::engine::*;
 //pub use crate::list::*;
 pub use crate::generator::*;
 pub use crate::generator_func::*;
+pub use crate::template::*;
+pub use crate::retry::*;
@@ -0,0 +1,82 @@
+use crate::error::*;
+use std::time::Duration;
+
+/// Wait between two attempts of a failing term.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backoff {
+    None,
+    Fixed(Duration),
+    /// Starts at `initial` and doubles after every attempt, up to `max`.
+    Exponential { initial: Duration, max: Duration }
+}
+
+impl Backoff {
+    /// Wait after the given failed attempt, counting from 1.
+    pub fn delay(&self, attempt: usize) -> Duration {
+        match *self {
+            Backoff::None => Duration::from_secs(0),
+            Backoff::Fixed(delay) => delay,
+            Backoff::Exponential { initial, max } => {
+                let factor = 1u32 << attempt.saturating_sub(1).min(31);
+                initial.checked_mul(factor).map_or(max, |delay| delay.min(max))
+            }
+        }
+    }
+}
+
+type RetryPredicate<'a, ErrorType> = Box<dyn Fn(&ErrorType) -> bool + 'a>;
+
+/// How often the engine runs the closure of a failing term before giving
+/// up. Only errors returned by the closure are retried, never engine errors
+/// or panics.
+pub struct RetryPolicy<'a, ErrorType> {
+    max_attempts: usize,
+    backoff: Backoff,
+    predicate: Option<RetryPredicate<'a, ErrorType>>
+}
+
+impl<'a, ErrorType> RetryPolicy<'a, ErrorType>
+where ErrorType: std::error::Error + 'static
+{
+    /// Run the closure at most `max_attempts` times, without waiting in
+    /// between.
+    pub fn new(max_attempts: usize) -> Self {
+        RetryPolicy {
+            max_attempts: max_attempts,
+            backoff: Backoff::None,
+            predicate: None
+        }
+    }
+
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Only retry errors for which `predicate` returns true.
+    pub fn retry_if<FnType>(mut self, predicate: FnType) -> Self
+    where FnType: Fn(&ErrorType) -> bool + 'a {
+        self.predicate = Some(Box::new(predicate));
+        self
+    }
+
+    pub fn max_attempts(&self) -> usize {
+        self.max_attempts
+    }
+
+    // Whether the attempt that failed with `error` is followed by another.
+    pub(crate) fn retries(&self, attempt: usize, error: &ExpressionError<ErrorType>) -> bool {
+        if attempt >= self.max_attempts {
+            return false;
+        }
+        match error {
+            ExpressionError::Eval(e) | ExpressionError::Element { error: e, .. } =>
+                self.predicate.as_ref().is_none_or(|predicate| predicate(e)),
+            _ => false
+        }
+    }
+
+    pub(crate) fn delay(&self, attempt: usize) -> Duration {
+        self.backoff.delay(attempt)
+    }
+}
@@ -1,40 +1,193 @@
 use crate::error::*;
 use crate::engine::*;
 use crate::generator::*;
+use crate::retry::*;
+use crate::diagnostics::{self, Diagnostic};
 use worm_cell::{AtomicWormCell, AtomicWormCellReader};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
 pub(crate) trait Expression<EvalErrorType>
+where EvalErrorType: std::error::Error + 'static
 {
     fn evaluated(&self) -> bool;
+    fn value_type(&self) -> &'static str;
     fn upstream(&self) -> &IndexSet;
-    fn eval(&self) -> Result<(), EvalErrorType>;
+    fn eval(&self) -> Result<(), ExpressionError<EvalErrorType>>;
+
+    // Upstream term chosen once the eager upstream has been evaluated, used
+    // by expressions that only need one of several branches.
+    fn branch(&self) -> Option<TermIndex> {
+        None
+    }
+
+    // Every term `branch` may return, kept alive by `retain_reachable`.
+    fn branches(&self) -> Vec<TermIndex> {
+        self.branch().into_iter().collect()
+    }
+
+    // Upstream term whose failure does not fail this expression: the
+    // engine passes its outcome to `recover` before calling `eval`.
+    fn source(&self) -> Option<TermIndex> {
+        None
+    }
+
+    // Error the source failed with, or None if it was evaluated.
+    fn recover(&self, _error: Option<ExpressionError<EvalErrorType>>) {
+        unreachable!("only expressions with a source recover")
+    }
+}
+
+
+type RetryObserver<'a, ErrorType> = Box<dyn Fn(&TermIndex, usize, &ExpressionError<ErrorType>) + 'a>;
+
+type ContextFn<'a> = Box<dyn Fn() -> String + 'a>;
+
+type Invariant<'a> = (String, Box<dyn Fn() -> bool + 'a>);
+
+type Expander<'a, ErrorType> = Box<dyn FnOnce(&mut SubgraphBuilder<'a, ErrorType>) + 'a>;
+
+/// Mode of an engine that evaluates its terms.
+pub struct Evaluating;
+
+/// Mode of the engine a subgraph closure builds terms in. It implements
+/// `Builder` but not `Engine`, so the closure cannot evaluate anything.
+pub struct Building;
+
+pub struct SimpleEngine<'a, ErrorType, Mode = Evaluating>
+where ErrorType: std::error::Error + 'static
+{
+    pub(crate) terms: Vec<Box<dyn Expression<ErrorType> + 'a>>,
+    // TermIndex of each entry of terms, and the reverse mapping. Indices
+    // stay valid when retain_reachable compacts terms.
+    pub(crate) ids: Vec<TermIndex>,
+    pub(crate) slots: HashMap<usize, usize>,
+    // Subgraph engines start at their parent's next_id, so their indices
+    // are already valid once spliced into the parent.
+    next_id: usize,
+    id: EngineId,
+    expanders: HashMap<usize, Expander<'a, ErrorType>>,
+    pub(crate) names: HashMap<usize, String>,
+    contexts: HashMap<usize, ContextFn<'a>>,
+    invariants: HashMap<usize, Vec<Invariant<'a>>>,
+    prefixes: Vec<String>,
+    pub(crate) failed: HashSet<usize>,
+    // Consumer of each term whose value has one, and the terms whose value
+    // was moved out, by a consumer or by take.
+    consumers: HashMap<usize, usize>,
+    pub(crate) consumed: HashSet<usize>,
+    // Errors of terms whose own evaluation failed, returned as
+    // ExpressionError::Failed until retry.
+    pub(crate) errors: HashMap<usize, Arc<ExpressionError<ErrorType>>>,
+    pub(crate) durations: HashMap<usize, Duration>,
+    retry_policies: HashMap<usize, RetryPolicy<'a, ErrorType>>,
+    // Closure runs of the last evaluation of each term, retries included.
+    pub(crate) attempts: HashMap<usize, usize>,
+    retry_observers: Vec<RetryObserver<'a, ErrorType>>,
+    diagnostics: Vec<Diagnostic>,
+    catch_panics: bool,
+    check_invariants: bool,
+    mode: PhantomData<Mode>
+}
+
+/// Engine whose terms may each fail with their own error type.
+pub type BoxedEngine<'a> = SimpleEngine<'a, BoxedError>;
+
+/// Builder passed to the closures of `Engine::subgraph`.
+pub type SubgraphBuilder<'a, ErrorType> = SimpleEngine<'a, ErrorType, Building>;
+
+static NEXT_ENGINE_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Identity of the engine a term belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EngineId(usize);
+
+impl EngineId {
+    fn next() -> EngineId {
+        EngineId(NEXT_ENGINE_ID.fetch_add(1, Ordering::Relaxed))
+    }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TermIndex(pub(crate) usize, pub(crate) EngineId);
+
+impl TermIndex {
+    pub fn index(&self) -> usize {
+        self.0
+    }
 
-pub struct SimpleEngine<'a, ErrorType> {
-    terms: Vec<Box<dyn Expression<ErrorType> + 'a>>,
+    pub fn engine(&self) -> EngineId {
+        self.1
+    }
 }
 
+/// Upstream terms of a new term. Sets made by `Engine::upstream` know their
+/// engine, so `add` rejects up front terms from another engine and terms
+/// whose value already has a consumer. In a set made by `TermSet::new`,
+/// such terms are reported when evaluating.
 #[derive(Clone)]
-pub struct TermIndex(usize);
+pub struct IndexSet(pub(crate) Vec<TermIndex>, Option<EngineId>, HashSet<usize>);
 
-pub struct IndexSet(Vec<TermIndex>);
+impl IndexSet {
+    // Upstream of a term made from `term` by the engine itself.
+    pub(crate) fn of(term: &TermIndex) -> IndexSet {
+        IndexSet(vec![term.clone()], None, HashSet::new())
+    }
+}
 
 impl TermSet for IndexSet {
     type TermImpl = TermIndex;
 
     fn new() -> IndexSet {
-        IndexSet(Vec::new())
+        IndexSet(Vec::new(), None, HashSet::new())
     }
 
-    fn add<'a, ValueType, TermType>(mut self, term: &'a TermType) -> Self
+    fn add<'a, ValueType, TermType>(mut self, term: &'a TermType) -> EngineResult<Self>
     where TermType: TermLike<'a, ValueType, TermIndex> {
-        self.0.push(term.get_implementation().clone());
-        self
+        match self.1 {
+            Some(engine) if engine != term.get_implementation().1 => Err(EngineError::ForeignTerm),
+            _ if self.2.contains(&term.get_implementation().0) => Err(EngineError::Consumed),
+            _ => {
+                self.0.push(term.get_implementation().clone());
+                Ok(self)
+            }
+        }
     }
 }
 
+/// Every failure of a keep-going evaluation.
+#[derive(Debug)]
+pub struct EvalFailures<EvalError>
+where EvalError: std::error::Error + 'static {
+    pub failed: Vec<(TermIndex, ExpressionError<EvalError>)>,
+    /// Terms not evaluated because an upstream term failed.
+    pub skipped: Vec<TermIndex>
+}
+
+impl<EvalError: std::error::Error + 'static> std::error::Error for EvalFailures<EvalError> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.failed.first().map(|(_, e)| e as &(dyn std::error::Error + 'static))
+    }
+}
+
+impl<EvalError: std::error::Error + 'static> fmt::Display for EvalFailures<EvalError> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} terms failed, {} skipped", self.failed.len(), self.skipped.len())?;
+        for (term, e) in &self.failed {
+            write!(f, "\n  term {}: {}", term.index(), e)?;
+        }
+        Ok(())
+    }
+}
+
+
 struct SimpleExpression<ValueType, FnType>
 {
     result: Arc<AtomicWormCell<ValueType>>,
@@ -63,176 +216,1367 @@ where
         self.result.is_set()
     }
 
+    fn value_type(&self) -> &'static str {
+        std::any::type_name::<ValueType>()
+    }
+
     fn upstream(&self) -> &IndexSet {
         &self.upstream
     }
 
-    fn eval(&self) -> Result<(), EvalErrorType> {
+    fn eval(&self) -> Result<(), ExpressionError<EvalErrorType>> {
         self.result.set((self.func)());
         Ok(())
     }
 }
 
-struct SimpleErrExpression<ValueType, FnType>
+// Like SimpleExpression, but the closure is dropped after its only run.
+struct OnceExpression<ValueType, FnType>
 {
     result: Arc<AtomicWormCell<ValueType>>,
-    func: FnType,
+    func: Cell<Option<FnType>>,
     upstream: IndexSet
 }
 
-impl<ValueType, ErrorType, FnType> SimpleErrExpression<ValueType, FnType>
-    where FnType: Fn() -> Result<ValueType, ErrorType>
+impl<ValueType, FnType> OnceExpression<ValueType, FnType>
+    where FnType: FnOnce() -> ValueType
 {
     fn new(func: FnType, upstream: IndexSet) -> Self {
-        SimpleErrExpression {
+        OnceExpression {
             result: Arc::new(AtomicWormCell::new()),
-            func: func,
+            func: Cell::new(Some(func)),
             upstream: upstream
         }
     }
 }
 
-impl<ValueType, ErrorType, FnType, EvalErrorType> Expression<EvalErrorType> for SimpleErrExpression<ValueType, FnType>
+impl<ValueType, FnType, EvalErrorType> Expression<EvalErrorType> for OnceExpression<ValueType, FnType>
 where
-    FnType: Fn() -> Result<ValueType, ErrorType>,
-    EvalErrorType: std::error::Error + From<ErrorType>
+    FnType: FnOnce() -> ValueType,
+    EvalErrorType: std::error::Error + 'static
 {
     fn evaluated(&self) -> bool {
         self.result.is_set()
     }
 
+    fn value_type(&self) -> &'static str {
+        std::any::type_name::<ValueType>()
+    }
+
     fn upstream(&self) -> &IndexSet {
         &self.upstream
     }
 
-    fn eval(&self) -> Result<(), EvalErrorType> {
-        self.result.set((self.func)()?);
+    fn eval(&self) -> Result<(), ExpressionError<EvalErrorType>> {
+        let func = self.func.take().ok_or(EngineError::OnceRerun)?;
+        self.result.set(func());
         Ok(())
     }
 }
 
-impl<'a, ErrorType> SimpleEngine<'a, ErrorType>
-where ErrorType: 'a + std::error::Error + 'static
+struct ConsumeExpression<ValueType, SourceType, FnType>
 {
-    pub fn new() -> SimpleEngine<'a, ErrorType> {
-        SimpleEngine { terms: Vec::new() }
+    result: Arc<AtomicWormCell<ValueType>>,
+    source: AtomicWormCellReader<Owned<SourceType>>,
+    func: Cell<Option<FnType>>,
+    upstream: IndexSet
+}
+
+impl<ValueType, SourceType, FnType> ConsumeExpression<ValueType, SourceType, FnType>
+    where FnType: FnOnce(SourceType) -> ValueType
+{
+    fn new(source: AtomicWormCellReader<Owned<SourceType>>, func: FnType, upstream: IndexSet) -> Self {
+        ConsumeExpression {
+            result: Arc::new(AtomicWormCell::new()),
+            source: source,
+            func: Cell::new(Some(func)),
+            upstream: upstream
+        }
     }
 }
 
-impl<'a, ET> Engine<'a> for SimpleEngine<'a, ET>
-where ET: 'a + std::error::Error + 'static
+impl<ValueType, SourceType, FnType, EvalErrorType> Expression<EvalErrorType> for ConsumeExpression<ValueType, SourceType, FnType>
+where
+    FnType: FnOnce(SourceType) -> ValueType,
+    EvalErrorType: std::error::Error + 'static
 {
-    type ErrorType = ET;
-    type UpstreamSet = IndexSet;
-    type TermImpl = TermIndex;
-    
+    fn evaluated(&self) -> bool {
+        self.result.is_set()
+    }
 
-    fn eval_impl(&self, term: &TermIndex) -> Result<(), ExpressionError<Self::ErrorType>> {
-        if !self.terms[term.0].evaluated() {
-            for subterm in &self.terms[term.0].upstream().0 {
-                self.eval_impl(&subterm)?;
-            }
+    fn value_type(&self) -> &'static str {
+        std::any::type_name::<ValueType>()
+    }
 
-            self.terms[term.0].eval().map_err(ExpressionError::Eval)
-        } else {
+    fn upstream(&self) -> &IndexSet {
+        &self.upstream
+    }
+
+    fn eval(&self) -> Result<(), ExpressionError<EvalErrorType>> {
+        let value = self.source.try_get()?.take()?;
+        let func = self.func.take().ok_or(EngineError::OnceRerun)?;
+        self.result.set(func(value));
+        Ok(())
+    }
+}
+
+// Evaluated once its source is, the value is read through the Projection
+// handle. It runs like any term, so it gets a duration and attempts.
+struct ProjectExpression<SourceType, ValueType>
+{
+    source: AtomicWormCellReader<SourceType>,
+    ran: Cell<bool>,
+    upstream: IndexSet,
+    phantom: PhantomData<ValueType>
+}
+
+impl<SourceType, ValueType, EvalErrorType> Expression<EvalErrorType> for ProjectExpression<SourceType, ValueType>
+where EvalErrorType: std::error::Error + 'static
+{
+    fn evaluated(&self) -> bool {
+        self.ran.get()
+    }
+
+    fn value_type(&self) -> &'static str {
+        std::any::type_name::<ValueType>()
+    }
+
+    fn upstream(&self) -> &IndexSet {
+        &self.upstream
+    }
+
+    fn eval(&self) -> Result<(), ExpressionError<EvalErrorType>> {
+        self.source.try_get()?;
+        self.ran.set(true);
+        Ok(())
+    }
+}
+
+// One output of a term computing several; the value is set by the shared
+// upstream term running the closure. Each output still runs on its own, so
+// its invariants are checked and its attempts recorded.
+struct OutputExpression<ValueType>
+{
+    result: Arc<AtomicWormCell<ValueType>>,
+    ran: Cell<bool>,
+    upstream: IndexSet
+}
+
+impl<ValueType> OutputExpression<ValueType> {
+    fn new(upstream: IndexSet) -> Self {
+        OutputExpression {
+            result: Arc::new(AtomicWormCell::new()),
+            ran: Cell::new(false),
+            upstream: upstream
+        }
+    }
+}
+
+impl<ValueType, EvalErrorType> Expression<EvalErrorType> for OutputExpression<ValueType>
+where EvalErrorType: std::error::Error + 'static
+{
+    fn evaluated(&self) -> bool {
+        self.ran.get()
+    }
+
+    fn value_type(&self) -> &'static str {
+        std::any::type_name::<ValueType>()
+    }
+
+    fn upstream(&self) -> &IndexSet {
+        &self.upstream
+    }
+
+    fn eval(&self) -> Result<(), ExpressionError<EvalErrorType>> {
+        if self.result.is_set() {
+            self.ran.set(true);
             Ok(())
+        } else {
+            Err(EngineError::GetNotCalculated.into())
+        }
+    }
+}
+
+struct ConstantExpression<ValueType>
+{
+    result: Arc<AtomicWormCell<ValueType>>,
+    upstream: IndexSet
+}
+
+impl<ValueType> ConstantExpression<ValueType> {
+    fn new(value: ValueType) -> Self {
+        let result = Arc::new(AtomicWormCell::new());
+        result.set(value);
+        ConstantExpression {
+            result: result,
+            upstream: IndexSet::new()
         }
     }
+}
 
-    fn scalar<'t, ValueType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> Term<'t, ValueType, Self::TermImpl>
-    where
-        ValueType: 'a,
-        FnType: Fn() -> ValueType + 'a,
-        'a: 't {
+impl<ValueType, EvalErrorType> Expression<EvalErrorType> for ConstantExpression<ValueType>
+where EvalErrorType: std::error::Error + 'static
+{
+    fn evaluated(&self) -> bool {
+        true
+    }
 
-        let expr = Box::new(SimpleExpression::new(func, upstream));
-        let term_result = AtomicWormCellReader::new(expr.result.clone());
-        self.terms.push(expr);
-        Term::new(term_result, TermIndex(self.terms.len() - 1))
+    fn value_type(&self) -> &'static str {
+        std::any::type_name::<ValueType>()
     }
 
-    fn scalar_err<ValueType, ErrorType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> Term<ValueType, Self::TermImpl>
-    where
-        ValueType: 'a,
-        FnType: Fn() -> Result<ValueType, ErrorType> + 'a,
-        Self::ErrorType: From<ErrorType> {
+    fn upstream(&self) -> &IndexSet {
+        &self.upstream
+    }
 
-        let expr = Box::new(SimpleErrExpression::new(func, upstream));
-        let term_result = AtomicWormCellReader::new(expr.result.clone());
-        self.terms.push(expr);
-        Term::new(term_result, TermIndex(self.terms.len() - 1))
+    fn eval(&self) -> Result<(), ExpressionError<EvalErrorType>> {
+        Ok(())
     }
+}
 
-    fn list<ElementType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> ListTerm<ElementType, Self::TermImpl>
-    where
-        ElementType: 'a,
-        FnType: Fn() -> Vec<ElementType> + 'a {
+struct SimpleErrExpression<ValueType, FnType>
+{
+    result: Arc<AtomicWormCell<ValueType>>,
+    func: FnType,
+    upstream: IndexSet
+}
 
-        let expr = Box::new(SimpleExpression::new(func, upstream));
-        let term_result = AtomicWormCellReader::new(expr.result.clone());
-        self.terms.push(expr);
-        ListTerm::new(term_result, TermIndex(self.terms.len() - 1))
+impl<ValueType, ErrorType, FnType> SimpleErrExpression<ValueType, FnType>
+    where FnType: Fn() -> Result<ValueType, ErrorType>
+{
+    fn new(func: FnType, upstream: IndexSet) -> Self {
+        SimpleErrExpression {
+            result: Arc::new(AtomicWormCell::new()),
+            func: func,
+            upstream: upstream
+        }
     }
+}
 
-    fn list_err<ElementType, ErrorType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> ListTerm<ElementType, Self::TermImpl>
-    where
-        ElementType: 'a,
-        FnType: Fn() -> Result<Vec<ElementType>, ErrorType> + 'a,
-        Self::ErrorType: From<ErrorType> {
+impl<ValueType, ErrorType, FnType, EvalErrorType> Expression<EvalErrorType> for SimpleErrExpression<ValueType, FnType>
+where
+    FnType: Fn() -> Result<ValueType, ErrorType>,
+    EvalErrorType: std::error::Error + From<ErrorType> + 'static
+{
+    fn evaluated(&self) -> bool {
+        self.result.is_set()
+    }
 
-        let expr = Box::new(SimpleErrExpression::new(func, upstream));
-        let term_result = AtomicWormCellReader::new(expr.result.clone());
-        self.terms.push(expr);
-        ListTerm::new(term_result, TermIndex(self.terms.len() - 1))
+    fn value_type(&self) -> &'static str {
+        std::any::type_name::<ValueType>()
     }
 
-    fn generator<'t, ElementType, GeneratorType>(&mut self, generator: GeneratorType, upstream: Self::UpstreamSet) -> ListTerm<'t, ElementType, Self::TermImpl>
-    where
-        ElementType: 'a,
-        GeneratorType: Generator<Item=ElementType> + 'a,
-        'a: 't {
+    fn upstream(&self) -> &IndexSet {
+        &self.upstream
+    }
 
-        let expr = Box::new(SimpleExpression::new(move || generator.iter().collect(), upstream));
-        let term_result = AtomicWormCellReader::new(expr.result.clone());
-        self.terms.push(expr);
-        ListTerm::new(term_result, TermIndex(self.terms.len() - 1))
+    fn eval(&self) -> Result<(), ExpressionError<EvalErrorType>> {
+        self.result.set((self.func)().map_err(|e| ExpressionError::Eval(e.into()))?);
+        Ok(())
     }
+}
 
-    fn generator_err<ElementType, ErrorType, GeneratorType>(&mut self, generator: GeneratorType, upstream: Self::UpstreamSet) -> ListTerm<ElementType, Self::TermImpl>
-    where
-        ElementType: 'a,
-        GeneratorType: Generator<Item=Result<ElementType, ErrorType>> + 'a,
-        Self::ErrorType: From<ErrorType> {
+// List expression whose errors carry the index of the failing element.
+struct ElementErrExpression<ElementType, FnType>
+{
+    result: Arc<AtomicWormCell<Vec<ElementType>>>,
+    func: FnType,
+    upstream: IndexSet
+}
 
-        let expr = Box::new(SimpleErrExpression::new(move || generator.iter().collect(), upstream));
-        let term_result = AtomicWormCellReader::new(expr.result.clone());
-        self.terms.push(expr);
-        ListTerm::new(term_result, TermIndex(self.terms.len() - 1))
+impl<ElementType, ErrorType, FnType> ElementErrExpression<ElementType, FnType>
+    where FnType: Fn() -> Result<Vec<ElementType>, (usize, ErrorType)>
+{
+    fn new(func: FnType, upstream: IndexSet) -> Self {
+        ElementErrExpression {
+            result: Arc::new(AtomicWormCell::new()),
+            func: func,
+            upstream: upstream
+        }
     }
+}
 
-    fn map<'t, SetupType, ElementType, GeneratorType, MapFnType>(&mut self, generator: GeneratorType, map_fn: MapFnType, upstream: Self::UpstreamSet) -> ListTerm<'t, ElementType, Self::TermImpl>
-    where
-        ElementType: 'a,
-        GeneratorType: Generator<Item=SetupType> + 'a,
-        MapFnType: Fn(SetupType) -> ElementType + 'a,
-        'a: 't {
+impl<ElementType, ErrorType, FnType, EvalErrorType> Expression<EvalErrorType> for ElementErrExpression<ElementType, FnType>
+where
+    FnType: Fn() -> Result<Vec<ElementType>, (usize, ErrorType)>,
+    EvalErrorType: std::error::Error + From<ErrorType> + 'static
+{
+    fn evaluated(&self) -> bool {
+        self.result.is_set()
+    }
 
-        let expr = Box::new(SimpleExpression::new(move || generator.iter().map(|s| map_fn(s)).collect(), upstream));
-        let term_result = AtomicWormCellReader::new(expr.result.clone());
-        self.terms.push(expr);
-        ListTerm::new(term_result, TermIndex(self.terms.len() - 1))
+    fn value_type(&self) -> &'static str {
+        std::any::type_name::<Vec<ElementType>>()
     }
 
-    fn map_err<SetupType, ElementType, ErrorType, GeneratorType, MapFnType>(&mut self, generator: GeneratorType, map_fn: MapFnType, upstream: Self::UpstreamSet) -> ListTerm<ElementType, Self::TermImpl>
-    where
-        ElementType: 'a,
-        GeneratorType: Generator<Item=Result<SetupType, ErrorType>> + 'a,
-        MapFnType: Fn(SetupType) -> Result<ElementType, ErrorType> + 'a,
-        Self::ErrorType: From<ErrorType> {
+    fn upstream(&self) -> &IndexSet {
+        &self.upstream
+    }
 
-        let expr = Box::new(SimpleErrExpression::new(move || generator.iter().map(|e| map_fn(e?)).collect(), upstream));
-        let term_result = AtomicWormCellReader::new(expr.result.clone());
-        self.terms.push(expr);
-        ListTerm::new(term_result, TermIndex(self.terms.len() - 1))
+    fn eval(&self) -> Result<(), ExpressionError<EvalErrorType>> {
+        let elements = (self.func)()
+            .map_err(|(index, e)| ExpressionError::Element { index: index, error: e.into() })?;
+        self.result.set(elements);
+        Ok(())
+    }
+}
+
+struct SwitchExpression<'a, KeyType, ValueType>
+{
+    result: Arc<AtomicWormCell<ValueType>>,
+    key: AtomicWormCellReader<KeyType>,
+    cases: Vec<(KeyType, Term<'a, ValueType, TermIndex>)>,
+    default: Term<'a, ValueType, TermIndex>,
+    upstream: IndexSet
+}
+
+impl<'a, KeyType, ValueType> SwitchExpression<'a, KeyType, ValueType>
+    where KeyType: PartialEq
+{
+    fn new(key: AtomicWormCellReader<KeyType>, cases: Vec<(KeyType, Term<'a, ValueType, TermIndex>)>, default: Term<'a, ValueType, TermIndex>, upstream: IndexSet) -> Self {
+        SwitchExpression {
+            result: Arc::new(AtomicWormCell::new()),
+            key: key,
+            cases: cases,
+            default: default,
+            upstream: upstream
+        }
+    }
+
+    // None until the key has been evaluated.
+    fn chosen(&self) -> Option<&Term<'a, ValueType, TermIndex>> {
+        let key = self.key.try_get().ok()?;
+        Some(self.cases.iter()
+            .find(|(case, _)| case == key)
+            .map(|(_, branch)| branch)
+            .unwrap_or(&self.default))
+    }
+}
+
+impl<'a, KeyType, ValueType, EvalErrorType> Expression<EvalErrorType> for SwitchExpression<'a, KeyType, ValueType>
+where
+    KeyType: PartialEq,
+    ValueType: Clone,
+    EvalErrorType: std::error::Error + 'static
+{
+    fn evaluated(&self) -> bool {
+        self.result.is_set()
+    }
+
+    fn value_type(&self) -> &'static str {
+        std::any::type_name::<ValueType>()
+    }
+
+    fn upstream(&self) -> &IndexSet {
+        &self.upstream
+    }
+
+    fn branch(&self) -> Option<TermIndex> {
+        self.chosen().map(|branch| branch.implementation.clone())
+    }
+
+    fn branches(&self) -> Vec<TermIndex> {
+        self.cases.iter()
+            .map(|(_, branch)| branch)
+            .chain(Some(&self.default))
+            .map(|branch| branch.implementation.clone())
+            .collect()
+    }
+
+    fn eval(&self) -> Result<(), ExpressionError<EvalErrorType>> {
+        let branch = self.chosen().ok_or(EngineError::GetNotCalculated)?;
+        self.result.set(branch.result.try_get()?.clone());
+        Ok(())
+    }
+}
+
+// Takes the value of a target term that is only known after construction:
+// a placeholder's binding or the output of a subgraph.
+struct ForwardExpression<'a, ValueType>
+{
+    result: Arc<AtomicWormCell<ValueType>>,
+    target: AtomicWormCellReader<Term<'a, ValueType, TermIndex>>,
+    upstream: IndexSet
+}
+
+impl<'a, ValueType> ForwardExpression<'a, ValueType> {
+    fn new(target: AtomicWormCellReader<Term<'a, ValueType, TermIndex>>, upstream: IndexSet) -> Self {
+        ForwardExpression {
+            result: Arc::new(AtomicWormCell::new()),
+            target: target,
+            upstream: upstream
+        }
+    }
+}
+
+impl<'a, ValueType, EvalErrorType> Expression<EvalErrorType> for ForwardExpression<'a, ValueType>
+where
+    ValueType: Clone,
+    EvalErrorType: std::error::Error + 'static
+{
+    fn evaluated(&self) -> bool {
+        self.result.is_set()
+    }
+
+    fn value_type(&self) -> &'static str {
+        std::any::type_name::<ValueType>()
+    }
+
+    fn upstream(&self) -> &IndexSet {
+        &self.upstream
+    }
+
+    fn branch(&self) -> Option<TermIndex> {
+        self.target.try_get().ok().map(|target| target.implementation.clone())
+    }
+
+    fn eval(&self) -> Result<(), ExpressionError<EvalErrorType>> {
+        let target = self.target.try_get().map_err(|_| EngineError::Unbound)?;
+        self.result.set(target.result.try_get()?.clone());
+        Ok(())
+    }
+}
+
+// Takes the value of its source, or recovers from the source's failure;
+// either way it is evaluated by `eval`, like any other term.
+struct RecoverExpression<'a, SourceType, ValueType, MapFnType, RecoverFnType, EvalErrorType>
+where EvalErrorType: std::error::Error + 'static
+{
+    result: Arc<AtomicWormCell<ValueType>>,
+    source: Term<'a, SourceType, TermIndex>,
+    source_error: RefCell<Option<ExpressionError<EvalErrorType>>>,
+    map_fn: MapFnType,
+    recover_fn: RecoverFnType,
+    upstream: IndexSet
+}
+
+impl<'a, SourceType, ValueType, MapFnType, RecoverFnType, EvalErrorType> RecoverExpression<'a, SourceType, ValueType, MapFnType, RecoverFnType, EvalErrorType>
+where EvalErrorType: std::error::Error + 'static
+{
+    fn new(source: Term<'a, SourceType, TermIndex>, map_fn: MapFnType, recover_fn: RecoverFnType) -> Self {
+        RecoverExpression {
+            result: Arc::new(AtomicWormCell::new()),
+            source: source,
+            source_error: RefCell::new(None),
+            map_fn: map_fn,
+            recover_fn: recover_fn,
+            upstream: IndexSet::new()
+        }
+    }
+}
+
+impl<'a, SourceType, ValueType, MapFnType, RecoverFnType, EvalErrorType> Expression<EvalErrorType> for RecoverExpression<'a, SourceType, ValueType, MapFnType, RecoverFnType, EvalErrorType>
+where
+    MapFnType: Fn(&SourceType) -> ValueType,
+    RecoverFnType: Fn(&ExpressionError<EvalErrorType>) -> ValueType,
+    EvalErrorType: std::error::Error + 'static
+{
+    fn evaluated(&self) -> bool {
+        self.result.is_set()
+    }
+
+    fn value_type(&self) -> &'static str {
+        std::any::type_name::<ValueType>()
+    }
+
+    fn upstream(&self) -> &IndexSet {
+        &self.upstream
+    }
+
+    fn source(&self) -> Option<TermIndex> {
+        Some(self.source.implementation.clone())
+    }
+
+    fn eval(&self) -> Result<(), ExpressionError<EvalErrorType>> {
+        let value = match &*self.source_error.borrow() {
+            Some(error) => (self.recover_fn)(error),
+            None => (self.map_fn)(self.source.result.try_get()?)
+        };
+        self.result.set(value);
+        Ok(())
+    }
+
+    fn recover(&self, error: Option<ExpressionError<EvalErrorType>>) {
+        *self.source_error.borrow_mut() = error;
+    }
+}
+
+impl<'a, ErrorType, Mode> SimpleEngine<'a, ErrorType, Mode>
+where ErrorType: 'a + std::error::Error + 'static
+{
+    fn with_first_id(first_id: usize, id: EngineId) -> Self {
+        SimpleEngine {
+            terms: Vec::new(),
+            ids: Vec::new(),
+            slots: HashMap::new(),
+            next_id: first_id,
+            id: id,
+            expanders: HashMap::new(),
+            names: HashMap::new(),
+            contexts: HashMap::new(),
+            invariants: HashMap::new(),
+            prefixes: Vec::new(),
+            failed: HashSet::new(),
+            consumers: HashMap::new(),
+            consumed: HashSet::new(),
+            errors: HashMap::new(),
+            durations: HashMap::new(),
+            retry_policies: HashMap::new(),
+            attempts: HashMap::new(),
+            retry_observers: Vec::new(),
+            diagnostics: Vec::new(),
+            catch_panics: false,
+            check_invariants: cfg!(debug_assertions),
+            mode: PhantomData
+        }
+    }
+
+    pub fn id(&self) -> EngineId {
+        self.id
+    }
+
+    pub(crate) fn expr(&self, term: &TermIndex) -> EngineResult<&(dyn Expression<ErrorType> + 'a)> {
+        if term.1 != self.id || term.0 >= self.next_id {
+            return Err(EngineError::ForeignTerm);
+        }
+        match self.slots.get(&term.0) {
+            Some(slot) => Ok(&*self.terms[*slot]),
+            None => Err(EngineError::TermRemoved)
+        }
+    }
+
+    // Adds one output of the multi-output term `joint`.
+    fn output<'t, ValueType>(&mut self, mut expr: OutputExpression<ValueType>, joint: &Term<'t, (), TermIndex>) -> Term<'t, ValueType, TermIndex>
+    where ValueType: 'a {
+        expr.upstream = IndexSet::of(&joint.implementation);
+        let term_result = AtomicWormCellReader::new(expr.result.clone());
+        Term::new(term_result, self.push(Box::new(expr)))
+    }
+
+    // Whether a term of this engine reads the value of `term`.
+    fn has_downstream(&self, term: &TermIndex) -> bool {
+        self.terms.iter().any(|expr| {
+            expr.upstream().0.contains(term) || expr.branches().contains(term) || expr.source().as_ref() == Some(term)
+        })
+    }
+
+    // Whether `term` is `upstream` or may depend on it, through bound
+    // placeholders and every branch of lazy terms too.
+    fn depends_on(&self, term: &TermIndex, upstream: &TermIndex) -> bool {
+        let mut visited = HashSet::new();
+        let mut pending = vec![term.clone()];
+        while let Some(index) = pending.pop() {
+            if index == *upstream {
+                return true;
+            }
+            if let (true, Ok(expr)) = (visited.insert(index.0), self.expr(&index)) {
+                pending.extend(expr.upstream().0.iter().cloned());
+                pending.extend(expr.branches());
+                pending.extend(expr.source());
+            }
+        }
+        false
+    }
+
+    fn push(&mut self, expr: Box<dyn Expression<ErrorType> + 'a>) -> TermIndex {
+        let id = TermIndex(self.next_id, self.id);
+        self.next_id += 1;
+        self.slots.insert(id.0, self.terms.len());
+        self.ids.push(id.clone());
+        self.terms.push(expr);
+        id
+    }
+
+    // Records the failure of `term`, caching errors of its own closures,
+    // and returns the error.
+    fn fail(&mut self, term: &TermIndex, e: ExpressionError<ErrorType>) -> ExpressionError<ErrorType> {
+        self.failed.insert(term.0);
+        let cached = e.is_failure();
+        let error = self.in_context(term, e);
+        if !cached {
+            return error;
+        }
+        let error = Arc::new(error);
+        self.errors.insert(term.0, error.clone());
+        ExpressionError::Failed { term: term.0, error: error }
+    }
+
+    fn check_invariants(&self, term: &TermIndex) -> EngineResult<()> {
+        if !self.check_invariants {
+            return Ok(());
+        }
+        for (invariant, check) in self.invariants.get(&term.0).into_iter().flatten() {
+            if !self.guard(term, check)? {
+                return Err(EngineError::InvariantViolated { term: term.0, invariant: invariant.clone() });
+            }
+        }
+        Ok(())
+    }
+
+    // Wraps `error` in the context of `term`, if it has one. If building
+    // the context panics, the panic is the context.
+    fn in_context(&self, term: &TermIndex, error: ExpressionError<ErrorType>) -> ExpressionError<ErrorType> {
+        match self.contexts.get(&term.0) {
+            Some(context) => {
+                let context = self.guard(term, context).unwrap_or_else(|panicked| panicked.to_string());
+                ExpressionError::Context { context: context, error: Box::new(error) }
+            },
+            None => error
+        }
+    }
+
+    // Runs a closure given for `term`: its own, or an expander, fallback,
+    // invariant, context or retry observer. A panic fails `term` with
+    // `EngineError::Panicked` when panics are caught.
+    fn guard<ResultType, FnType>(&self, term: &TermIndex, func: FnType) -> EngineResult<ResultType>
+    where FnType: FnOnce() -> ResultType {
+        if !self.catch_panics {
+            return Ok(func());
+        }
+
+        panic::catch_unwind(AssertUnwindSafe(func)).map_err(|payload| {
+            let message = match payload.downcast::<String>() {
+                Ok(message) => *message,
+                Err(payload) => payload.downcast_ref::<&str>().map_or("unknown panic", |m| *m).to_string()
+            };
+            EngineError::Panicked { term: term.0, message: message }
+        })
+    }
+}
+
+impl<'a, ErrorType> SimpleEngine<'a, ErrorType>
+where ErrorType: 'a + std::error::Error + 'static
+{
+    pub fn new() -> SimpleEngine<'a, ErrorType> {
+        SimpleEngine::with_first_id(0, EngineId::next())
+    }
+
+    /// When set, a panic in a term's closure fails that term with
+    /// `EngineError::Panicked` instead of unwinding through `eval`.
+    pub fn set_catch_panics(&mut self, catch_panics: bool) {
+        self.catch_panics = catch_panics;
+    }
+
+    /// Whether invariants are checked, by default only in debug builds.
+    pub fn set_check_invariants(&mut self, check_invariants: bool) {
+        self.check_invariants = check_invariants;
+    }
+
+    /// Run the closure of `term` again when it fails, as `policy` allows.
+    pub fn set_retry_policy(&mut self, term: &TermIndex, policy: RetryPolicy<'a, ErrorType>) -> EngineResult<()> {
+        self.expr(term)?;
+        self.retry_policies.insert(term.0, policy);
+        Ok(())
+    }
+
+    /// Call `observer` with the term, the attempt number counting from 1
+    /// and the error every time a failed attempt is about to be retried.
+    pub fn on_retry<FnType>(&mut self, observer: FnType)
+    where FnType: Fn(&TermIndex, usize, &ExpressionError<ErrorType>) + 'a {
+        self.retry_observers.push(Box::new(observer));
+    }
+
+    /// Diagnostics emitted by every closure run so far, retries excluded.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
+    }
+
+    /// Like `eval`, also returning the diagnostics emitted by the closures
+    /// this evaluation ran.
+    pub fn eval_diagnosed<'t, ValueType, TermType>(&mut self, term: &'t TermType) -> (Result<&'t ValueType, ExpressionError<ErrorType>>, Vec<Diagnostic>)
+    where
+        TermType: TermLike<'t, ValueType, TermIndex>,
+        'a: 't {
+        let start = self.diagnostics.len();
+        let result = self.eval(term);
+        (result, self.diagnostics[start..].to_vec())
+    }
+
+    // Evaluates a single term whose upstream is already evaluated,
+    // recording its duration and failure.
+    fn run(&mut self, term: &TermIndex) -> Result<(), ExpressionError<ErrorType>> {
+        self.cached_error(term)?;
+
+        // Only the closure runs are timed, not retry delays or invariants.
+        let mut duration = Duration::from_secs(0);
+        let mut attempt = 1;
+        let (result, diagnostics) = loop {
+            let start = Instant::now();
+            let (result, diagnostics) = diagnostics::collect(term, || self.eval_expr(term));
+            duration += start.elapsed();
+            match (&result, self.retry_policies.get(&term.0)) {
+                (Err(e), Some(policy)) if policy.retries(attempt, e) => {
+                    let observed = self.retry_observers.iter()
+                        .try_for_each(|observer| self.guard(term, || observer(term, attempt, e)));
+                    if let Err(panicked) = observed {
+                        break (Err(panicked.into()), diagnostics);
+                    }
+                    thread::sleep(policy.delay(attempt));
+                    attempt += 1;
+                },
+                _ => break (result, diagnostics)
+            }
+        };
+        self.diagnostics.extend(diagnostics);
+        let result = result.and_then(|()| Ok(self.check_invariants(term)?));
+        self.durations.insert(term.0, duration);
+        self.attempts.insert(term.0, attempt);
+        match result {
+            Ok(()) => {
+                self.failed.remove(&term.0);
+                let sources: Vec<usize> = self.expr(term)?.upstream().0.iter()
+                    .filter(|upstream| self.consumers.get(&upstream.0) == Some(&term.0))
+                    .map(|upstream| upstream.0)
+                    .collect();
+                self.consumed.extend(sources);
+                Ok(())
+            },
+            Err(e) => Err(self.fail(term, e))
+        }
+    }
+
+    fn cached_error(&self, term: &TermIndex) -> Result<(), ExpressionError<ErrorType>> {
+        match self.errors.get(&term.0) {
+            Some(error) => Err(ExpressionError::Failed { term: term.0, error: error.clone() }),
+            None => Ok(())
+        }
+    }
+
+    // Like `run`, but a term with a source evaluates it first and recovers
+    // from its failure. Engine errors are not failures of the source and
+    // are passed on.
+    fn run_or_recover(&mut self, term: &TermIndex) -> Result<(), ExpressionError<ErrorType>> {
+        if let Some(source) = self.expr(term)?.source() {
+            let error = match self.eval_impl(&source) {
+                Ok(()) => None,
+                Err(e) if e.is_failure() => Some(e),
+                Err(e) => return Err(self.in_context(term, e))
+            };
+            self.expr(term)?.recover(error);
+        }
+        self.run(term)
+    }
+
+    /// Forget the cached failure of `term`, so the next evaluation runs its
+    /// closure again. A term that violated an invariant keeps its value and
+    /// its failure, and retrying it fails with `EngineError::DoubleCalc`.
+    pub fn retry(&mut self, term: &TermIndex) -> EngineResult<()> {
+        if self.expr(term)?.evaluated() {
+            return if self.failed.contains(&term.0) { Err(EngineError::DoubleCalc) } else { Ok(()) };
+        }
+        self.errors.remove(&term.0);
+        self.failed.remove(&term.0);
+        Ok(())
+    }
+
+    /// Evaluate `term` like `make -k`: a failure does not stop the
+    /// evaluation of terms that do not depend on it. On failure, returns
+    /// every term that failed and every term skipped because one of its
+    /// upstream terms failed.
+    pub fn eval_keep_going<'t, ValueType, TermType>(&mut self, term: &'t TermType) -> Result<&'t ValueType, EvalFailures<ErrorType>>
+    where TermType: TermLike<'t, ValueType, TermIndex> {
+        let mut failures = EvalFailures { failed: Vec::new(), skipped: Vec::new() };
+        if self.keep_going_impl(term.get_implementation(), &mut HashMap::new(), &mut failures) {
+            term.try_get().map_err(|e| EvalFailures { failed: vec![(term.get_implementation().clone(), e.into())], skipped: Vec::new() })
+        } else {
+            Err(failures)
+        }
+    }
+
+    // Returns whether `term` is evaluated; `visited` holds the outcome of
+    // every term already visited by this evaluation.
+    fn keep_going_impl(&mut self, term: &TermIndex, visited: &mut HashMap<usize, bool>, failures: &mut EvalFailures<ErrorType>) -> bool {
+        if let Some(evaluated) = visited.get(&term.0) {
+            return *evaluated;
+        }
+        if let Err(e) = self.check_consumed(term) {
+            failures.failed.push((term.clone(), e.into()));
+            visited.insert(term.0, false);
+            return false;
+        }
+
+        let upstream = match self.expr(term) {
+            Ok(expr) if expr.evaluated() => {
+                let cached = self.cached_error(term);
+                let evaluated = cached.is_ok();
+                if let Err(e) = cached {
+                    failures.failed.push((term.clone(), e));
+                    visited.insert(term.0, false);
+                }
+                return evaluated;
+            },
+            Ok(expr) => expr.upstream().clone(),
+            Err(e) => {
+                failures.failed.push((term.clone(), e.into()));
+                visited.insert(term.0, false);
+                return false;
+            }
+        };
+
+        let mut ready = true;
+        for subterm in &upstream.0 {
+            ready &= self.keep_going_impl(subterm, visited, failures);
+        }
+        if ready {
+            if let Err(e) = self.expand(term) {
+                failures.failed.push((term.clone(), e));
+                visited.insert(term.0, false);
+                return false;
+            }
+            if let Some(branch) = self.expr(term).ok().and_then(|expr| expr.branch()) {
+                ready = self.keep_going_impl(&branch, visited, failures);
+            }
+        }
+
+        let evaluated = if !ready {
+            failures.skipped.push(term.clone());
+            false
+        } else if let Err(e) = self.run_or_recover(term) {
+            failures.failed.push((term.clone(), e));
+            false
+        } else {
+            true
+        };
+        visited.insert(term.0, evaluated);
+        evaluated
+    }
+
+    // Fails with Consumed if the value of `term` was moved out, or if
+    // `term` reads a value that belongs to another term's consumer.
+    pub(crate) fn check_consumed(&self, term: &TermIndex) -> EngineResult<()> {
+        if self.consumed.contains(&term.0) {
+            return Err(EngineError::Consumed);
+        }
+        if let Ok(expr) = self.expr(term) {
+            let mut upstream = expr.upstream().0.clone();
+            upstream.extend(expr.branches());
+            upstream.extend(expr.source());
+            if upstream.iter().any(|upstream| self.consumers.get(&upstream.0).is_some_and(|consumer| *consumer != term.0)) {
+                return Err(EngineError::Consumed);
+            }
+        }
+        Ok(())
+    }
+
+    fn eval_expr(&self, term: &TermIndex) -> Result<(), ExpressionError<ErrorType>> {
+        let expr = self.expr(term)?;
+        self.guard(term, || expr.eval())?
+    }
+
+    // Runs the closure of subgraph term `term`, if it has not run yet, and
+    // appends the terms it builds to this engine.
+    fn expand(&mut self, term: &TermIndex) -> Result<(), ExpressionError<ErrorType>> {
+        if let Some(expander) = self.expanders.remove(&term.0) {
+            let mut subgraph = SubgraphBuilder::with_first_id(self.next_id, self.id);
+            subgraph.catch_panics = self.catch_panics;
+            subgraph.check_invariants = self.check_invariants;
+            subgraph.consumers = self.consumers.clone();
+            if let Err(e) = self.guard(term, || expander(&mut subgraph)) {
+                return Err(self.fail(term, e.into()));
+            }
+            for (id, expr) in subgraph.ids.into_iter().zip(subgraph.terms) {
+                self.slots.insert(id.0, self.terms.len());
+                self.ids.push(id);
+                self.terms.push(expr);
+            }
+            self.next_id = subgraph.next_id;
+            self.expanders.extend(subgraph.expanders);
+            self.names.extend(subgraph.names);
+            self.contexts.extend(subgraph.contexts);
+            self.invariants.extend(subgraph.invariants);
+            self.retry_policies.extend(subgraph.retry_policies);
+            self.failed.extend(subgraph.failed);
+            self.errors.extend(subgraph.errors);
+            self.consumers.extend(subgraph.consumers);
+        }
+        Ok(())
+    }
+
+    /// Drop every term that is not a target or upstream of one, including
+    /// branches a lazy term might still choose. Handles to dropped terms
+    /// get `EngineError::TermRemoved` from the engine; handles to kept
+    /// terms remain valid.
+    pub fn retain_reachable(&mut self, targets: &[&TermIndex]) -> EngineResult<()> {
+        let mut keep = HashSet::new();
+        let mut pending: Vec<TermIndex> = targets.iter().map(|target| (*target).clone()).collect();
+        while let Some(term) = pending.pop() {
+            let expr = self.expr(&term)?;
+            if keep.insert(term.0) {
+                pending.extend(expr.upstream().0.iter().cloned());
+                pending.extend(expr.branches());
+                pending.extend(expr.source());
+            }
+        }
+
+        let terms = std::mem::take(&mut self.terms);
+        let ids = std::mem::take(&mut self.ids);
+        self.slots.clear();
+        for (id, expr) in ids.into_iter().zip(terms) {
+            if keep.contains(&id.0) {
+                self.slots.insert(id.0, self.terms.len());
+                self.ids.push(id);
+                self.terms.push(expr);
+            }
+        }
+
+        self.expanders.retain(|id, _| keep.contains(id));
+        self.names.retain(|id, _| keep.contains(id));
+        self.contexts.retain(|id, _| keep.contains(id));
+        self.invariants.retain(|id, _| keep.contains(id));
+        self.failed.retain(|id| keep.contains(id));
+        self.consumers.retain(|id, consumer| keep.contains(id) && keep.contains(consumer));
+        self.consumed.retain(|id| keep.contains(id));
+        self.errors.retain(|id, _| keep.contains(id));
+        self.durations.retain(|id, _| keep.contains(id));
+        self.retry_policies.retain(|id, _| keep.contains(id));
+        self.attempts.retain(|id, _| keep.contains(id));
+        Ok(())
+    }
+}
+
+impl<'a, ET, Mode> Builder<'a> for SimpleEngine<'a, ET, Mode>
+where ET: 'a + std::error::Error + 'static
+{
+    type ErrorType = ET;
+    type UpstreamSet = IndexSet;
+    type TermImpl = TermIndex;
+    type Subgraph = SubgraphBuilder<'a, ET>;
+
+    fn upstream(&self) -> IndexSet {
+        IndexSet(Vec::new(), Some(self.id), self.consumers.keys().cloned().collect())
+    }
+
+    fn name<'t, ValueType, TermType>(&mut self, term: &'t TermType, name: &str) -> EngineResult<()>
+    where TermType: TermLike<'t, ValueType, Self::TermImpl> {
+        self.expr(term.get_implementation())?;
+        let mut full_name = self.prefixes.join("/");
+        if !full_name.is_empty() {
+            full_name.push('/');
+        }
+        full_name.push_str(name);
+        self.names.insert(term.get_implementation().0, full_name);
+        Ok(())
+    }
+
+    fn term_name(&self, term: &TermIndex) -> Option<&str> {
+        self.expr(term).ok()?;
+        self.names.get(&term.0).map(String::as_str)
+    }
+
+    fn context<'t, ValueType, TermType>(&mut self, term: &'t TermType, context: &str) -> EngineResult<()>
+    where TermType: TermLike<'t, ValueType, Self::TermImpl> {
+        let context = context.to_string();
+        self.with_context(term, move || context.clone())
+    }
+
+    fn with_context<'t, ValueType, TermType, FnType>(&mut self, term: &'t TermType, context: FnType) -> EngineResult<()>
+    where
+        TermType: TermLike<'t, ValueType, Self::TermImpl>,
+        FnType: Fn() -> String + 'a {
+        self.expr(term.get_implementation())?;
+        self.contexts.insert(term.get_implementation().0, Box::new(context));
+        Ok(())
+    }
+
+    fn invariant<'t, TermType, FnType>(&mut self, term: &'t TermType, invariant: &str, check: FnType) -> EngineResult<()>
+    where
+        TermType: TermValue + TermLike<'t, TermType::ValueType, Self::TermImpl>,
+        TermType::ValueType: 'a,
+        FnType: Fn(&TermType::ValueType) -> bool + 'a {
+        let index = term.get_implementation();
+        let evaluated = self.expr(index)?.evaluated();
+        let value = term.reader();
+        let check = move || value.try_get().map_or(true, &check);
+        self.invariants.entry(index.0)
+            .or_default()
+            .push((invariant.to_string(), Box::new(check)));
+
+        // Terms evaluated from the start, like constants, never run again.
+        if evaluated && !self.failed.contains(&index.0) {
+            if let Err(e) = self.check_invariants(index) {
+                self.fail(index, e.clone().into());
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn push_prefix(&mut self, prefix: &str) {
+        self.prefixes.push(prefix.to_string());
+    }
+
+    fn pop_prefix(&mut self) {
+        self.prefixes.pop();
+    }
+
+    fn scalar<'t, ValueType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> Term<'t, ValueType, Self::TermImpl>
+    where
+        ValueType: 'a,
+        FnType: Fn() -> ValueType + 'a,
+        'a: 't {
+
+        let expr = Box::new(SimpleExpression::new(func, upstream));
+        let term_result = AtomicWormCellReader::new(expr.result.clone());
+        Term::new(term_result, self.push(expr))
+    }
+
+    fn scalar_err<'t, ValueType, ErrorType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> Term<'t, ValueType, Self::TermImpl>
+    where
+        ValueType: 'a,
+        FnType: Fn() -> Result<ValueType, ErrorType> + 'a,
+        Self::ErrorType: From<ErrorType>,
+        'a: 't {
+
+        let expr = Box::new(SimpleErrExpression::new(func, upstream));
+        let term_result = AtomicWormCellReader::new(expr.result.clone());
+        Term::new(term_result, self.push(expr))
+    }
+
+    fn scalar2<'t, FirstType, SecondType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> (Term<'t, FirstType, Self::TermImpl>, Term<'t, SecondType, Self::TermImpl>)
+    where
+        FirstType: 'a,
+        SecondType: 'a,
+        FnType: Fn() -> (FirstType, SecondType) + 'a,
+        'a: 't {
+
+        let first = OutputExpression::new(IndexSet::new());
+        let second = OutputExpression::new(IndexSet::new());
+        let (first_result, second_result) = (first.result.clone(), second.result.clone());
+        let joint = self.scalar(move || {
+            let (a, b) = func();
+            first_result.set(a);
+            second_result.set(b);
+        }, upstream);
+        (self.output(first, &joint), self.output(second, &joint))
+    }
+
+    fn scalar3<'t, FirstType, SecondType, ThirdType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> (Term<'t, FirstType, Self::TermImpl>, Term<'t, SecondType, Self::TermImpl>, Term<'t, ThirdType, Self::TermImpl>)
+    where
+        FirstType: 'a,
+        SecondType: 'a,
+        ThirdType: 'a,
+        FnType: Fn() -> (FirstType, SecondType, ThirdType) + 'a,
+        'a: 't {
+
+        let first = OutputExpression::new(IndexSet::new());
+        let second = OutputExpression::new(IndexSet::new());
+        let third = OutputExpression::new(IndexSet::new());
+        let (first_result, second_result, third_result) = (first.result.clone(), second.result.clone(), third.result.clone());
+        let joint = self.scalar(move || {
+            let (a, b, c) = func();
+            first_result.set(a);
+            second_result.set(b);
+            third_result.set(c);
+        }, upstream);
+        (self.output(first, &joint), self.output(second, &joint), self.output(third, &joint))
+    }
+
+    fn list<'t, ElementType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> ListTerm<'t, ElementType, Self::TermImpl>
+    where
+        ElementType: 'a,
+        FnType: Fn() -> Vec<ElementType> + 'a,
+        'a: 't {
+
+        let expr = Box::new(SimpleExpression::new(func, upstream));
+        let term_result = AtomicWormCellReader::new(expr.result.clone());
+        ListTerm::new(term_result, self.push(expr))
+    }
+
+    fn list_err<'t, ElementType, ErrorType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> ListTerm<'t, ElementType, Self::TermImpl>
+    where
+        ElementType: 'a,
+        FnType: Fn() -> Result<Vec<ElementType>, ErrorType> + 'a,
+        Self::ErrorType: From<ErrorType>,
+        'a: 't {
+
+        let expr = Box::new(SimpleErrExpression::new(func, upstream));
+        let term_result = AtomicWormCellReader::new(expr.result.clone());
+        ListTerm::new(term_result, self.push(expr))
+    }
+
+    fn generator<'t, ElementType, GeneratorType>(&mut self, generator: GeneratorType, upstream: Self::UpstreamSet) -> ListTerm<'t, ElementType, Self::TermImpl>
+    where
+        ElementType: 'a,
+        GeneratorType: Generator<Item=ElementType> + 'a,
+        'a: 't {
+
+        let expr = Box::new(SimpleExpression::new(move || generator.iter().collect(), upstream));
+        let term_result = AtomicWormCellReader::new(expr.result.clone());
+        ListTerm::new(term_result, self.push(expr))
+    }
+
+    fn generator_err<'t, ElementType, ErrorType, GeneratorType>(&mut self, generator: GeneratorType, upstream: Self::UpstreamSet) -> ListTerm<'t, ElementType, Self::TermImpl>
+    where
+        ElementType: 'a,
+        GeneratorType: Generator<Item=Result<ElementType, ErrorType>> + 'a,
+        Self::ErrorType: From<ErrorType>,
+        'a: 't {
+
+        let expr = Box::new(ElementErrExpression::new(move || {
+            generator.iter()
+                .enumerate()
+                .map(|(index, element)| element.map_err(|e| (index, e)))
+                .collect()
+        }, upstream));
+        let term_result = AtomicWormCellReader::new(expr.result.clone());
+        ListTerm::new(term_result, self.push(expr))
+    }
+
+    fn constant<'t, ValueType>(&mut self, value: ValueType) -> Term<'t, ValueType, Self::TermImpl>
+    where
+        ValueType: 'a,
+        'a: 't {
+
+        let expr = Box::new(ConstantExpression::new(value));
+        let term_result = AtomicWormCellReader::new(expr.result.clone());
+        Term::new(term_result, self.push(expr))
+    }
+
+    fn scalar_once<'t, ValueType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> Term<'t, ValueType, Self::TermImpl>
+    where
+        ValueType: 'a,
+        FnType: FnOnce() -> ValueType + 'a,
+        'a: 't {
+
+        let expr = Box::new(OnceExpression::new(func, upstream));
+        let term_result = AtomicWormCellReader::new(expr.result.clone());
+        Term::new(term_result, self.push(expr))
+    }
+
+    fn list_once<'t, ElementType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> ListTerm<'t, ElementType, Self::TermImpl>
+    where
+        ElementType: 'a,
+        FnType: FnOnce() -> Vec<ElementType> + 'a,
+        'a: 't {
+
+        let expr = Box::new(OnceExpression::new(func, upstream));
+        let term_result = AtomicWormCellReader::new(expr.result.clone());
+        ListTerm::new(term_result, self.push(expr))
+    }
+
+    fn generator_once<'t, ElementType, IterType>(&mut self, elements: IterType, upstream: Self::UpstreamSet) -> ListTerm<'t, ElementType, Self::TermImpl>
+    where
+        ElementType: 'a,
+        IterType: IntoIterator<Item=ElementType> + 'a,
+        'a: 't {
+
+        let expr = Box::new(OnceExpression::new(move || elements.into_iter().collect(), upstream));
+        let term_result = AtomicWormCellReader::new(expr.result.clone());
+        ListTerm::new(term_result, self.push(expr))
+    }
+
+    fn map<'t, SetupType, ElementType, GeneratorType, MapFnType>(&mut self, generator: GeneratorType, map_fn: MapFnType, upstream: Self::UpstreamSet) -> ListTerm<'t, ElementType, Self::TermImpl>
+    where
+        ElementType: 'a,
+        GeneratorType: Generator<Item=SetupType> + 'a,
+        MapFnType: Fn(SetupType) -> ElementType + 'a,
+        'a: 't {
+
+        let expr = Box::new(SimpleExpression::new(move || {
+            generator.iter()
+                .enumerate()
+                .map(|(index, s)| diagnostics::in_element(index, || map_fn(s)))
+                .collect()
+        }, upstream));
+        let term_result = AtomicWormCellReader::new(expr.result.clone());
+        ListTerm::new(term_result, self.push(expr))
+    }
+
+    fn map_err<'t, SetupType, ElementType, ErrorType, GeneratorType, MapFnType>(&mut self, generator: GeneratorType, map_fn: MapFnType, upstream: Self::UpstreamSet) -> ListTerm<'t, ElementType, Self::TermImpl>
+    where
+        ElementType: 'a,
+        GeneratorType: Generator<Item=Result<SetupType, ErrorType>> + 'a,
+        MapFnType: Fn(SetupType) -> Result<ElementType, ErrorType> + 'a,
+        Self::ErrorType: From<ErrorType>,
+        'a: 't {
+
+        let expr = Box::new(ElementErrExpression::new(move || {
+            generator.iter()
+                .enumerate()
+                .map(|(index, setup)| setup.and_then(|s| diagnostics::in_element(index, || map_fn(s))).map_err(|e| (index, e)))
+                .collect()
+        }, upstream));
+        let term_result = AtomicWormCellReader::new(expr.result.clone());
+        ListTerm::new(term_result, self.push(expr))
+    }
+
+    fn generator_partial<'t, ElementType, ErrorType, GeneratorType>(&mut self, generator: GeneratorType, upstream: Self::UpstreamSet) -> Term<'t, PartialList<ElementType, ErrorType>, Self::TermImpl>
+    where
+        ElementType: 'a,
+        ErrorType: 'a,
+        GeneratorType: Generator<Item=Result<ElementType, ErrorType>> + 'a,
+        'a: 't {
+
+        let expr = Box::new(SimpleExpression::new(move || generator.iter().collect(), upstream));
+        let term_result = AtomicWormCellReader::new(expr.result.clone());
+        Term::new(term_result, self.push(expr))
+    }
+
+    fn map_partial<'t, SetupType, ElementType, ErrorType, GeneratorType, MapFnType>(&mut self, generator: GeneratorType, map_fn: MapFnType, upstream: Self::UpstreamSet) -> Term<'t, PartialList<ElementType, ErrorType>, Self::TermImpl>
+    where
+        ElementType: 'a,
+        ErrorType: 'a,
+        GeneratorType: Generator<Item=Result<SetupType, ErrorType>> + 'a,
+        MapFnType: Fn(SetupType) -> Result<ElementType, ErrorType> + 'a,
+        'a: 't {
+
+        let expr = Box::new(SimpleExpression::new(move || {
+            generator.iter()
+                .enumerate()
+                .map(|(index, e)| e.and_then(|s| diagnostics::in_element(index, || map_fn(s))))
+                .collect()
+        }, upstream));
+        let term_result = AtomicWormCellReader::new(expr.result.clone());
+        Term::new(term_result, self.push(expr))
+    }
+
+    fn recover<'t, ValueType, FnType>(&mut self, term: &Term<'t, ValueType, Self::TermImpl>, func: FnType) -> Term<'t, ValueType, Self::TermImpl>
+    where
+        ValueType: Clone + 'a,
+        FnType: Fn(&ExpressionError<Self::ErrorType>) -> ValueType + 'a,
+        'a: 't {
+
+        let source = Term::new(term.result.clone(), term.implementation.clone());
+        let expr = Box::new(RecoverExpression::new(source, ValueType::clone, func));
+        let term_result = AtomicWormCellReader::new(expr.result.clone());
+        Term::new(term_result, self.push(expr))
+    }
+
+    fn consume<'t, ValueType, ResultType, FnType>(&mut self, term: &Term<'t, Owned<ValueType>, Self::TermImpl>, func: FnType) -> EngineResult<Term<'t, ResultType, Self::TermImpl>>
+    where
+        ValueType: 'a,
+        ResultType: 'a,
+        FnType: FnOnce(ValueType) -> ResultType + 'a,
+        'a: 't {
+
+        self.expr(&term.implementation)?;
+        if self.consumers.contains_key(&term.implementation.0) || self.has_downstream(&term.implementation) {
+            return Err(EngineError::Consumed);
+        }
+        let expr = Box::new(ConsumeExpression::new(term.result.clone(), func, IndexSet::of(&term.implementation)));
+        let term_result = AtomicWormCellReader::new(expr.result.clone());
+        let index = self.push(expr);
+        self.consumers.insert(term.implementation.0, index.0);
+        Ok(Term::new(term_result, index))
+    }
+
+    fn optional<'t, ValueType>(&mut self, term: &Term<'t, ValueType, Self::TermImpl>) -> Term<'t, Option<ValueType>, Self::TermImpl>
+    where
+        ValueType: Clone + 'a,
+        'a: 't {
+
+        let source = Term::new(term.result.clone(), term.implementation.clone());
+        let expr = Box::new(RecoverExpression::new(source, |value: &ValueType| Some(value.clone()), |_: &ExpressionError<ET>| None));
+        let term_result = AtomicWormCellReader::new(expr.result.clone());
+        Term::new(term_result, self.push(expr))
+    }
+
+    fn switch<'t, KeyType, ValueType>(&mut self, key: &Term<'t, KeyType, Self::TermImpl>, cases: Vec<(KeyType, &Term<'t, ValueType, Self::TermImpl>)>, default: &Term<'t, ValueType, Self::TermImpl>) -> Term<'t, ValueType, Self::TermImpl>
+    where
+        KeyType: PartialEq + 'a,
+        ValueType: Clone + 'a,
+        'a: 't {
+
+        let cases = cases.into_iter()
+            .map(|(case, term)| (case, Term::new(term.result.clone(), term.implementation.clone())))
+            .collect();
+        let default = Term::new(default.result.clone(), default.implementation.clone());
+
+        let expr = Box::new(SwitchExpression::new(key.result.clone(), cases, default, IndexSet::of(&key.implementation)));
+        let term_result = AtomicWormCellReader::new(expr.result.clone());
+        Term::new(term_result, self.push(expr))
+    }
+
+    fn subgraph<'t, ValueType, FnType>(&mut self, func: FnType, upstream: Self::UpstreamSet) -> Term<'t, ValueType, Self::TermImpl>
+    where
+        ValueType: Clone + 'a,
+        FnType: FnOnce(&mut Self::Subgraph) -> Term<'a, ValueType, Self::TermImpl> + 'a,
+        'a: 't {
+
+        let target = Arc::new(AtomicWormCell::new());
+        let expr = Box::new(ForwardExpression::new(AtomicWormCellReader::new(target.clone()), upstream));
+        let term_result = AtomicWormCellReader::new(expr.result.clone());
+        let index = self.push(expr);
+
+        let prefixes = self.prefixes.clone();
+        self.expanders.insert(index.0, Box::new(move |subgraph: &mut Self::Subgraph| {
+            subgraph.prefixes = prefixes;
+            target.set(func(subgraph));
+        }));
+        Term::new(term_result, index)
+    }
+
+    fn project<'t, TermType, ValueType, FnType>(&mut self, term: &'t TermType, func: FnType) -> Projection<'a, TermType::ValueType, ValueType, Self::TermImpl>
+    where
+        TermType: TermValue + TermLike<'t, TermType::ValueType, Self::TermImpl>,
+        TermType::ValueType: 'a,
+        ValueType: 'a,
+        FnType: Fn(&TermType::ValueType) -> &ValueType + 'a {
+
+        let expr = Box::new(ProjectExpression::<_, ValueType> {
+            source: term.reader(),
+            ran: Cell::new(false),
+            upstream: IndexSet::of(term.get_implementation()),
+            phantom: PhantomData
+        });
+        Projection::new(term.reader(), Arc::new(func), self.push(expr))
+    }
+
+    fn placeholder<ValueType>(&mut self) -> Placeholder<'a, ValueType, Self::TermImpl>
+    where ValueType: Clone + 'a {
+
+        let target = Arc::new(AtomicWormCell::new());
+        let expr = Box::new(ForwardExpression::new(AtomicWormCellReader::new(target.clone()), self.upstream()));
+        let term_result = AtomicWormCellReader::new(expr.result.clone());
+        Placeholder::new(Term::new(term_result, self.push(expr)), target)
+    }
+
+    fn bind<'t, ValueType>(&mut self, placeholder: &Placeholder<'a, ValueType, Self::TermImpl>, term: &Term<'t, ValueType, Self::TermImpl>) -> Result<(), EngineError> {
+        self.expr(&placeholder.term.implementation)?;
+        self.expr(&term.implementation)?;
+        if self.depends_on(&term.implementation, &placeholder.term.implementation) {
+            return Err(EngineError::Cycle);
+        }
+        Ok(placeholder.target.try_set(Term::new(term.result.clone(), term.implementation.clone()))?)
+    }
+}
+
+impl<'a, ET> Engine<'a> for SimpleEngine<'a, ET>
+where ET: 'a + std::error::Error + 'static
+{
+    fn eval_impl(&mut self, term: &TermIndex) -> Result<(), ExpressionError<Self::ErrorType>> {
+        self.check_consumed(term)?;
+        if !self.expr(term)?.evaluated() {
+            for subterm in &self.expr(term)?.upstream().clone().0 {
+                self.eval_impl(&subterm).map_err(|e| self.in_context(term, e))?;
+            }
+
+            self.expand(term)?;
+            if let Some(branch) = self.expr(term)?.branch() {
+                self.eval_impl(&branch).map_err(|e| self.in_context(term, e))?;
+            }
+
+            self.run_or_recover(term)
+        } else {
+            self.cached_error(term)
+        }
+    }
+
+    fn take<'t, ValueType, TermType>(&mut self, term: &'t TermType) -> Result<ValueType, ExpressionError<Self::ErrorType>>
+    where
+        TermType: TermLike<'t, Owned<ValueType>, Self::TermImpl>,
+        ValueType: 't,
+        'a: 't {
+        let index = term.get_implementation();
+        if self.consumers.contains_key(&index.0) {
+            return Err(EngineError::Consumed.into());
+        }
+        let value = self.eval(term)?.take()?;
+        self.consumed.insert(index.0);
+        Ok(value)
     }
 }
@@ -0,0 +1,42 @@
+use crate::engine::*;
+use std::marker::PhantomData;
+
+/// A reusable piece of graph: a build function applied to a builder once
+/// per instance. The build function takes whatever terms the template needs
+/// as inputs and returns its outputs; both are plain values chosen by the
+/// caller, so the inputs must exist before `instantiate`. Use placeholders
+/// as inputs to bind them afterwards.
+///
+/// The only thing an instance adds to the build function is a name prefix:
+/// names given inside the template are prefixed with the instance name.
+/// Contexts, invariants and unnamed terms are not affected.
+pub struct Template<'a, BuilderType, InputType, OutputType> {
+    build: Box<dyn Fn(&mut BuilderType, InputType) -> OutputType + 'a>
+}
+
+impl<'a, BuilderType, InputType, OutputType> Template<'a, BuilderType, InputType, OutputType>
+where BuilderType: Builder<'a>
+{
+    pub fn new<FnType>(build: FnType) -> Self
+    where FnType: Fn(&mut BuilderType, InputType) -> OutputType + 'a {
+        Template { build: Box::new(build) }
+    }
+
+    pub fn instantiate(&self, builder: &mut BuilderType, instance: &str, inputs: InputType) -> OutputType {
+        builder.push_prefix(instance);
+        let guard = PrefixGuard(builder, PhantomData);
+        (self.build)(&mut *guard.0, inputs)
+    }
+}
+
+// Pops the instance prefix, also when the build function panics.
+struct PrefixGuard<'a, 'b, BuilderType>(&'b mut BuilderType, PhantomData<&'a ()>)
+where BuilderType: Builder<'a>;
+
+impl<'a, 'b, BuilderType> Drop for PrefixGuard<'a, 'b, BuilderType>
+where BuilderType: Builder<'a>
+{
+    fn drop(&mut self) {
+        self.0.pop_prefix();
+    }
+}
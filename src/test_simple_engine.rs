@@ -0,0 +1,703 @@
+#[cfg(test)]
+mod tests {
+    use crate::engine::*;
+    use crate::simple_engine::*;
+    use crate::error::*;
+    use crate::template::*;
+    use crate::introspection::*;
+    use crate::analysis::*;
+    use crate::retry::*;
+    use crate::diagnostics::{self, Severity};
+    use crate::generator::*;
+    use std::cell::Cell;
+    use std::time::Duration;
+    use std::error::Error;
+    use std::num::ParseIntError;
+
+    #[test]
+    fn select_evaluates_one_branch() {
+        let else_calls = Cell::new(0);
+        let mut engine = SimpleEngine::<OpError>::new();
+
+        let cond = engine.scalar(|| true, engine.upstream());
+        let then_term = engine.scalar(|| 1, engine.upstream());
+        let else_term = engine.scalar(|| { else_calls.set(else_calls.get() + 1); 2 }, engine.upstream());
+        let chosen = engine.select(&cond, &then_term, &else_term);
+
+        assert_eq!(*engine.eval(&chosen).unwrap(), 1);
+        assert_eq!(else_calls.get(), 0);
+    }
+
+    #[test]
+    fn switch_falls_back_to_default() {
+        let mut engine = SimpleEngine::<OpError>::new();
+
+        let key = engine.scalar(|| "c", engine.upstream());
+        let a = engine.scalar(|| 1, engine.upstream());
+        let b = engine.scalar(|| 2, engine.upstream());
+        let default = engine.scalar(|| 0, engine.upstream());
+        let chosen = engine.switch(&key, vec![("a", &a), ("b", &b)], &default);
+
+        assert_eq!(*engine.eval(&chosen).unwrap(), 0);
+    }
+
+    #[test]
+    fn subgraph_built_during_eval() {
+        let mut engine = SimpleEngine::<OpError>::new();
+
+        let count = engine.scalar(|| 4, engine.upstream());
+        let reader = count.clone();
+        engine.push_prefix("report");
+        let total = engine.subgraph(move |subgraph| {
+            let parts: Vec<_> = (0..*reader)
+                .map(|i| subgraph.scalar(move || i * 10, subgraph.upstream()))
+                .collect();
+            let upstream = parts.iter().try_fold(subgraph.upstream(), |set, part| set.add(part)).unwrap();
+            let sum = subgraph.scalar(move || parts.iter().map(|part| **part).sum::<i32>(), upstream);
+            subgraph.name(&sum, "sum").unwrap();
+            sum
+        }, engine.upstream().add(&count).unwrap());
+        engine.pop_prefix();
+
+        assert_eq!(*engine.eval(&total).unwrap(), 60);
+        assert!(engine.terms().iter().any(|term| term.name.as_deref() == Some("report/sum")));
+    }
+
+    #[test]
+    fn template_instances_are_prefixed() {
+        let mut engine = SimpleEngine::<OpError>::new();
+
+        let double = Template::new(|engine: &mut SimpleEngine<OpError>, input: Term<i32, TermIndex>| {
+            let reader = input.clone();
+            let doubled = engine.scalar(move || *reader * 2, engine.upstream().add(&input).unwrap());
+            engine.name(&doubled, "doubled").unwrap();
+            doubled
+        });
+
+        let a = engine.scalar(|| 3, engine.upstream());
+        let b = engine.scalar(|| 5, engine.upstream());
+        let a2 = double.instantiate(&mut engine, "a", a);
+        let b2 = double.instantiate(&mut engine, "b", b);
+
+        assert_eq!(engine.term_name(a2.get_implementation()), Some("a/doubled"));
+        assert_eq!(engine.term_name(b2.get_implementation()), Some("b/doubled"));
+        assert_eq!(*engine.eval(&a2).unwrap(), 6);
+        assert_eq!(*engine.eval(&b2).unwrap(), 10);
+
+        let broken = Template::new(|_: &mut SimpleEngine<OpError>, _: ()| panic!("bad template"));
+        let built = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| broken.instantiate(&mut engine, "broken", ())));
+        assert!(built.is_err());
+        let c = engine.scalar(|| 7, engine.upstream());
+        engine.name(&c, "c").unwrap();
+        assert_eq!(engine.term_name(c.get_implementation()), Some("c"));
+    }
+
+    #[test]
+    fn placeholder_bound_after_use() {
+        let mut engine = SimpleEngine::<OpError>::new();
+
+        let input = engine.placeholder::<i32>();
+        let reader = input.term().clone();
+        let plus_one = engine.scalar(move || *reader + 1, engine.upstream().add(&input).unwrap());
+
+        match engine.eval(&plus_one) {
+            Err(ExpressionError::Engine(EngineError::Unbound)) => (),
+            _ => panic!("expected an unbound placeholder error")
+        }
+
+        let real = engine.scalar(|| 41, engine.upstream());
+        engine.bind(&input, &real).unwrap();
+        assert_eq!(*engine.eval(&plus_one).unwrap(), 42);
+    }
+
+    #[test]
+    fn bind_rejects_cycles() {
+        let mut engine = SimpleEngine::<OpError>::new();
+
+        let input = engine.placeholder::<i32>();
+        let reader = input.term().clone();
+        let plus_one = engine.scalar(move || *reader + 1, engine.upstream().add(&input).unwrap());
+        let doubled = engine.scalar(|| 2, engine.upstream().add(&plus_one).unwrap());
+
+        match engine.bind(&input, &doubled) {
+            Err(EngineError::Cycle) => (),
+            _ => panic!("expected a cycle error")
+        }
+        assert!(engine.bind(&input, input.term()).is_err());
+
+        let key = engine.scalar(|| true, engine.upstream());
+        let other = engine.scalar(|| 0, engine.upstream());
+        let chosen = engine.select(&key, input.term(), &other);
+        match engine.bind(&input, &chosen) {
+            Err(EngineError::Cycle) => (),
+            _ => panic!("expected a cycle error through an unchosen branch")
+        }
+
+        let real = engine.scalar(|| 1, engine.upstream());
+        engine.bind(&input, &real).unwrap();
+        assert_eq!(*engine.eval(&doubled).unwrap(), 2);
+    }
+
+    #[test]
+    fn eval_many_keeps_partial_results() {
+        let mut engine = SimpleEngine::<OpError>::new();
+
+        let good = engine.scalar(|| 1, engine.upstream());
+        let bad = engine.placeholder::<i32>();
+        let results = engine.eval_many(&[&good, bad.term()]);
+
+        assert_eq!(**results[0].as_ref().unwrap(), 1);
+        assert!(results[1].is_err());
+    }
+
+    #[test]
+    fn eval_tuple_of_mixed_types() {
+        let mut engine = SimpleEngine::<OpError>::new();
+
+        let number = engine.scalar(|| 2, engine.upstream());
+        let items = engine.scalar(|| vec!["a", "b"], engine.upstream());
+        let (number, items) = engine.eval_tuple((&number, &items));
+
+        assert_eq!(*number.unwrap(), 2);
+        assert_eq!(*items.unwrap(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn introspect_diamond() {
+        let mut engine = SimpleEngine::<OpError>::new();
+
+        let top = engine.scalar(|| 1, engine.upstream());
+        let left = engine.scalar(|| 2, engine.upstream().add(&top).unwrap());
+        let right = engine.scalar(|| 3.0, engine.upstream().add(&top).unwrap());
+        let bottom = engine.scalar(|| (), engine.upstream().add(&left).unwrap().add(&right).unwrap());
+        engine.name(&bottom, "bottom").unwrap();
+        engine.eval(&left).unwrap();
+
+        let terms = engine.terms();
+        assert_eq!(terms.len(), 4);
+        assert_eq!(terms[0].downstream, vec![left.implementation.clone(), right.implementation.clone()]);
+        assert_eq!(terms[1].state, TermState::Evaluated);
+        assert_eq!(terms[2].state, TermState::Pending);
+        assert_eq!(terms[2].value_type, "f64");
+        assert_eq!(terms[3].name.as_deref(), Some("bottom"));
+
+        assert_eq!(engine.ancestors(&bottom.implementation).unwrap().len(), 3);
+        assert_eq!(engine.descendants(&top.implementation).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn plan_skips_evaluated_terms() {
+        let calls = Cell::new(0);
+        let mut engine = SimpleEngine::<OpError>::new();
+
+        let a = engine.scalar(|| { calls.set(calls.get() + 1); 1 }, engine.upstream());
+        let b = engine.scalar(|| { calls.set(calls.get() + 1); 2 }, engine.upstream());
+        let c = engine.scalar(|| { calls.set(calls.get() + 1); 3 }, engine.upstream().add(&a).unwrap().add(&b).unwrap());
+        let d = engine.scalar(|| { calls.set(calls.get() + 1); 4 }, engine.upstream().add(&c).unwrap().add(&a).unwrap());
+        engine.eval(&b).unwrap();
+        calls.set(0);
+
+        let plan = engine.plan(&d.implementation).unwrap();
+        assert_eq!(calls.get(), 0);
+        assert_eq!(plan.order, vec![a.implementation.clone(), c.implementation.clone(), d.implementation.clone()]);
+        assert_eq!(plan.levels, vec![vec![a.implementation.clone()], vec![c.implementation.clone()], vec![d.implementation.clone()]]);
+
+        let broken = engine.scalar_err(|| Err::<i32, OpError>(OpError::NeverError), engine.upstream());
+        let after = engine.scalar(|| 5, engine.upstream().add(&broken).unwrap());
+        assert!(engine.eval(&broken).is_err());
+        assert!(engine.plan(&broken.implementation).unwrap().order.is_empty());
+        assert!(engine.plan(&after.implementation).unwrap().order.is_empty());
+    }
+
+    #[test]
+    fn stats_and_critical_path() {
+        let mut engine = SimpleEngine::<OpError>::new();
+
+        let top = engine.scalar(|| 1, engine.upstream());
+        let fast = engine.scalar(|| 2, engine.upstream().add(&top).unwrap());
+        let slow = engine.scalar(|| { std::thread::sleep(Duration::from_millis(20)); 3 }, engine.upstream().add(&top).unwrap());
+        let bottom = engine.scalar(|| 4, engine.upstream().add(&fast).unwrap().add(&slow).unwrap());
+        engine.eval(&bottom).unwrap();
+
+        let stats: GraphStats = engine.stats();
+        assert_eq!(stats.depth, 3);
+        assert_eq!(stats.widths, vec![1, 2, 1]);
+        assert_eq!(stats.max_width(), 2);
+        assert_eq!(stats.fan_in[&1], 2);
+        assert_eq!(stats.fan_out[&2], 1);
+
+        let path = engine.critical_path();
+        assert_eq!(path.terms, vec![top.implementation.clone(), slow.implementation.clone(), bottom.implementation.clone()]);
+        assert!(path.duration >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn retain_reachable_drops_orphans() {
+        let mut engine = SimpleEngine::<OpError>::new();
+
+        let orphan = engine.scalar(|| 1, engine.upstream());
+        let input = engine.scalar(|| 2, engine.upstream());
+        let output = engine.scalar(|| 3, engine.upstream().add(&input).unwrap());
+        engine.retain_reachable(&[&output.implementation]).unwrap();
+
+        assert_eq!(engine.terms().len(), 2);
+        assert_eq!(*engine.eval(&output).unwrap(), 3);
+        match engine.eval(&orphan) {
+            Err(ExpressionError::Engine(EngineError::TermRemoved)) => (),
+            _ => panic!("expected a removed term error")
+        }
+
+        let later = engine.scalar(|| 4, engine.upstream().add(&output).unwrap());
+        assert_eq!(*engine.eval(&later).unwrap(), 4);
+    }
+
+    #[test]
+    fn foreign_terms_are_rejected() {
+        let mut engine = SimpleEngine::<OpError>::new();
+        let mut other = SimpleEngine::<OpError>::new();
+
+        let local = engine.scalar(|| 1, engine.upstream());
+        let foreign = other.scalar(|| 2, other.upstream());
+
+        match engine.eval(&foreign) {
+            Err(ExpressionError::Engine(EngineError::ForeignTerm)) => (),
+            _ => panic!("expected a foreign term error")
+        }
+        assert!(engine.upstream().add(&local).is_ok());
+        match engine.upstream().add(&foreign) {
+            Err(EngineError::ForeignTerm) => (),
+            _ => panic!("expected a foreign term error")
+        }
+        assert!(engine.name(&foreign, "foreign").is_err());
+        assert!(engine.context(&foreign, "loading").is_err());
+        assert!(engine.invariant(&foreign, "positive", |v: &i32| *v > 0).is_err());
+        assert_eq!(engine.term_name(&foreign.implementation), None);
+
+        let mixed = engine.scalar(|| 3, IndexSet::new().add(&foreign).unwrap());
+        assert!(engine.eval(&mixed).is_err());
+    }
+
+    #[test]
+    fn panicking_term_fails_alone() {
+        let mut engine = SimpleEngine::<OpError>::new();
+        engine.set_catch_panics(true);
+
+        let bad = engine.scalar(|| -> i32 { panic!("bad record") }, engine.upstream());
+        let good = engine.scalar(|| 1, engine.upstream());
+        let results = engine.eval_many(&[&bad, &good]);
+
+        match results[0].as_ref().unwrap_err().original() {
+            ExpressionError::Engine(EngineError::Panicked { term, message }) => {
+                assert_eq!(*term, bad.implementation.index());
+                assert_eq!(message, "bad record");
+            },
+            _ => panic!("expected a panicked term error")
+        }
+        assert_eq!(**results[1].as_ref().unwrap(), 1);
+        assert_eq!(engine.term_info(&bad.implementation).unwrap().state, TermState::Failed);
+    }
+
+    #[test]
+    fn panicking_callbacks_fail_their_term() {
+        let mut engine = SimpleEngine::<OpError>::new();
+        engine.set_catch_panics(true);
+        engine.set_check_invariants(true);
+
+        let built = engine.subgraph(|_| -> Term<i32, TermIndex> { panic!("bad subgraph") }, engine.upstream());
+        let checked = engine.scalar(|| 1, engine.upstream());
+        engine.invariant(&checked, "checked", |_: &i32| panic!("bad invariant")).unwrap();
+        let failing = engine.scalar_err(|| Err::<i32, OpError>(OpError::NeverError), engine.upstream());
+        let recovered = engine.recover(&failing, |_| panic!("bad fallback"));
+        engine.with_context(&failing, || panic!("bad context")).unwrap();
+
+        for (term, message) in [(&built, "bad subgraph"), (&checked, "bad invariant"), (&recovered, "bad fallback")] {
+            let error = engine.eval(term).unwrap_err();
+            assert!(error.to_string().contains(message), "{}", error);
+        }
+        assert!(engine.eval(&failing).unwrap_err().to_string().contains("bad context"));
+    }
+
+    #[test]
+    fn keep_going_collects_independent_failures() {
+        let calls = Cell::new(0);
+        let mut engine = SimpleEngine::<OpError>::new();
+
+        let broken_a = engine.scalar_err(|| Err::<i32, OpError>(OpError::NeverError), engine.upstream());
+        let broken_b = engine.scalar_err(|| Err::<i32, OpError>(OpError::NeverError), engine.upstream());
+        let fine = engine.scalar(|| { calls.set(calls.get() + 1); 1 }, engine.upstream());
+        let after_a = engine.scalar(|| 2, engine.upstream().add(&broken_a).unwrap());
+        let all = engine.scalar(|| 3, engine.upstream().add(&after_a).unwrap().add(&broken_b).unwrap().add(&fine).unwrap());
+
+        let failures = engine.eval_keep_going(&all).err().unwrap();
+        let failed: Vec<_> = failures.failed.iter().map(|(term, _)| term.clone()).collect();
+        assert_eq!(failed, vec![broken_a.implementation.clone(), broken_b.implementation.clone()]);
+        assert!(failures.failed.iter().all(|(_, e)| matches!(e.original(), ExpressionError::Eval(OpError::NeverError))));
+        assert_eq!(failures.skipped, vec![after_a.implementation.clone(), all.implementation.clone()]);
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn failures_are_cached_until_retry() {
+        let calls = Cell::new(0);
+        let mut engine = SimpleEngine::<OpError>::new();
+
+        let flaky = engine.scalar_err(|| {
+            calls.set(calls.get() + 1);
+            if calls.get() == 1 { Err(OpError::NeverError) } else { Ok(1) }
+        }, engine.upstream());
+        let downstream = engine.scalar(|| 2, engine.upstream().add(&flaky).unwrap());
+
+        let first = match engine.eval(&downstream) {
+            Err(ExpressionError::Failed { term, error }) => {
+                assert_eq!(term, flaky.implementation.index());
+                assert!(matches!(*error, ExpressionError::Eval(OpError::NeverError)));
+                error
+            },
+            _ => panic!("expected the closure's error")
+        };
+        match engine.eval(&downstream) {
+            Err(ExpressionError::Failed { error, .. }) => assert!(std::sync::Arc::ptr_eq(&error, &first)),
+            _ => panic!("expected the cached failure")
+        }
+        assert_eq!(calls.get(), 1);
+
+        engine.retry(&flaky.implementation).unwrap();
+        assert_eq!(*engine.eval(&downstream).unwrap(), 2);
+        assert_eq!(calls.get(), 2);
+    }
+
+    // Generates `make(i)` for i in 0..count.
+    struct Counter<T>(usize, fn(usize) -> T);
+
+    impl<T> Generator for Counter<T> {
+        type Item = T;
+        type Iter<'g> = std::iter::Map<std::ops::Range<usize>, fn(usize) -> T> where Self: 'g;
+
+        fn iter<'g>(&'g self) -> Self::Iter<'g> {
+            (0..self.0).map(self.1)
+        }
+    }
+
+    fn fails_at(index: usize, failing: &[usize]) -> Result<usize, OpError> {
+        if failing.contains(&index) { Err(OpError::NeverError) } else { Ok(index) }
+    }
+
+    #[test]
+    fn list_errors_carry_lowest_failing_index() {
+        let mut engine = SimpleEngine::<OpError>::new();
+
+        let setups = Counter(4, |i| fails_at(i, &[2]));
+        let mapped = engine.map_err(setups, |s| fails_at(s, &[1]).map(|s| s * 10), engine.upstream());
+        let generated = engine.generator_err(Counter(3, |i| fails_at(i, &[1, 2])), engine.upstream());
+
+        match engine.eval(&mapped).unwrap_err().original() {
+            ExpressionError::Element { index, .. } => assert_eq!(*index, 1),
+            _ => panic!("expected an element error")
+        }
+        match engine.eval(&generated).unwrap_err().original() {
+            ExpressionError::Element { index, .. } => assert_eq!(*index, 1),
+            _ => panic!("expected an element error")
+        }
+    }
+
+    #[test]
+    fn partial_terms_keep_going_past_failing_elements() {
+        let mut engine = SimpleEngine::<OpError>::new();
+
+        let setups = Counter(4, |i| fails_at(i, &[1]));
+        let mapped = engine.map_partial(setups, |s| fails_at(s, &[2]).map(|s| s * 10), engine.upstream());
+        let generated = engine.generator_partial(Counter(2, |i| fails_at(i, &[1])), engine.upstream());
+
+        let mapped = engine.eval(&mapped).unwrap();
+        assert_eq!(mapped.elements, vec![0, 30]);
+        assert_eq!(mapped.errors.iter().map(|(index, _)| *index).collect::<Vec<_>>(), vec![1, 2]);
+        let generated = engine.eval(&generated).unwrap();
+        assert_eq!(generated.elements, vec![0]);
+        assert!(!generated.is_complete());
+    }
+
+    #[test]
+    fn partial_list_reports_failing_indices() {
+        let partial: PartialList<i32, &str> = vec![Ok(1), Err("bad"), Ok(3), Err("worse")].into_iter().collect();
+
+        assert_eq!(partial.elements, vec![1, 3]);
+        assert_eq!(partial.errors, vec![(1, "bad"), (3, "worse")]);
+        assert!(!partial.is_complete());
+    }
+
+    #[test]
+    fn recover_from_failed_upstream() {
+        let mut engine = SimpleEngine::<OpError>::new();
+
+        let live = engine.scalar_err(|| Err::<i32, OpError>(OpError::NeverError), engine.upstream());
+        let config = engine.recover(&live, |_| 7);
+        let maybe = engine.optional(&live);
+        let config_value = config.clone();
+        let doubled = engine.scalar(move || *config_value * 2, engine.upstream().add(&config).unwrap());
+
+        assert_eq!(*engine.eval(&doubled).unwrap(), 14);
+        assert_eq!(*engine.eval(&maybe).unwrap(), None);
+        assert_eq!(engine.term_info(&live.implementation).unwrap().state, TermState::Failed);
+        assert!(engine.term_error(&live.implementation).is_some());
+        assert_eq!(engine.term_info(&config.implementation).unwrap().attempts, 1);
+        assert!(engine.duration(&config.implementation).is_some());
+
+        let unbound = engine.placeholder::<i32>();
+        let fallback = engine.recover(unbound.term(), |_| 0);
+        match engine.eval(&fallback) {
+            Err(ExpressionError::Engine(EngineError::Unbound)) => (),
+            _ => panic!("engine errors must not be recovered from")
+        }
+    }
+
+    #[test]
+    fn retry_policy_reruns_failing_closure() {
+        let calls = Cell::new(0);
+        let retries = Cell::new(0);
+        let mut engine = SimpleEngine::<OpError>::new();
+
+        let flaky = engine.scalar_err(|| {
+            calls.set(calls.get() + 1);
+            if calls.get() < 3 { Err(OpError::NeverError) } else { Ok(5) }
+        }, engine.upstream());
+        engine.set_retry_policy(&flaky.implementation, RetryPolicy::new(3)
+            .backoff(Backoff::Exponential { initial: Duration::from_millis(20), max: Duration::from_millis(40) })).unwrap();
+        engine.on_retry(|_, _, _| retries.set(retries.get() + 1));
+
+        assert_eq!(*engine.eval(&flaky).unwrap(), 5);
+        assert_eq!(retries.get(), 2);
+        assert_eq!(engine.term_info(&flaky.implementation).unwrap().attempts, 3);
+        assert!(engine.duration(&flaky.implementation).unwrap() < Duration::from_millis(20));
+        assert_eq!(Backoff::Exponential { initial: Duration::from_millis(1), max: Duration::from_millis(3) }.delay(3),
+                   Duration::from_millis(3));
+    }
+
+    #[test]
+    fn boxed_engine_mixes_error_types() {
+        let mut engine = BoxedEngine::new();
+
+        let parsed = engine.scalar_err(|| -> Result<i32, Box<dyn Error + Send + Sync>> {
+            Ok("x1".parse::<i32>()?)
+        }, engine.upstream());
+        let checked = engine.scalar_err(|| -> Result<i32, Box<dyn Error + Send + Sync>> {
+            Err(Box::new(OpError::NeverError))
+        }, engine.upstream());
+
+        assert!(engine.eval(&parsed).unwrap_err().is::<ParseIntError>());
+        let error = engine.eval(&checked).unwrap_err();
+        assert!(error.downcast_ref::<OpError>().is_some());
+        assert!(!error.is::<ParseIntError>());
+
+        let doubled = engine.scalar(|| 2, engine.upstream().add(&parsed).unwrap());
+        let tripled = engine.scalar(|| 3, engine.upstream().add(&parsed).unwrap());
+        for result in engine.eval_many(&[&doubled, &tripled]) {
+            let error = result.unwrap_err();
+            assert!(error.is::<ParseIntError>());
+            assert!(error.source().is_some());
+        }
+    }
+
+    #[test]
+    fn context_chains_through_downstream_terms() {
+        let mut engine = BoxedEngine::new();
+
+        let sales = engine.scalar_err(|| -> Result<i32, Box<dyn Error + Send + Sync>> {
+            Ok("12x".parse::<i32>()?)
+        }, engine.upstream());
+        engine.context(&sales, "loading sales.csv").unwrap();
+        let sales_value = sales.clone();
+        let report = engine.scalar(move || *sales_value + 1, engine.upstream().add(&sales).unwrap());
+        engine.with_context(&report, || format!("building {}", "report")).unwrap();
+
+        let error = engine.eval(&report).unwrap_err();
+        assert_eq!(error.to_string(), "building report: loading sales.csv: invalid digit found in string");
+        assert!(error.is::<ParseIntError>());
+        assert!(error.source().is_some());
+        assert_eq!(engine.eval(&report).unwrap_err().to_string(), "building report: loading sales.csv: invalid digit found in string");
+    }
+
+    #[test]
+    fn diagnostics_are_tagged_with_term() {
+        let mut engine = SimpleEngine::<OpError>::new();
+
+        let rows = engine.scalar(|| {
+            diagnostics::warning("3 rows skipped");
+            7
+        }, engine.upstream());
+        let rows_value = rows.clone();
+        let total = engine.scalar(move || *rows_value * 2, engine.upstream().add(&rows).unwrap());
+
+        let (result, emitted) = engine.eval_diagnosed(&total);
+        assert_eq!(*result.unwrap(), 14);
+        assert_eq!(emitted.len(), 1);
+        assert_eq!(emitted[0].term, rows.implementation);
+        assert_eq!(emitted[0].severity, Severity::Warning);
+        assert_eq!(emitted[0].element, None);
+        diagnostics::note("dropped outside evaluation");
+        assert_eq!(engine.take_diagnostics().len(), 1);
+    }
+
+    #[test]
+    fn map_diagnostics_are_tagged_with_element() {
+        let mut engine = SimpleEngine::<OpError>::new();
+
+        let squares = engine.map(Counter(3, |i| i), |i| {
+            if i == 1 {
+                diagnostics::note("odd input");
+            }
+            i * i
+        }, engine.upstream());
+
+        let (result, emitted) = engine.eval_diagnosed(&squares);
+        assert_eq!(*result.unwrap(), vec![0, 1, 4]);
+        assert_eq!(emitted.len(), 1);
+        assert_eq!(emitted[0].term, squares.implementation);
+        assert_eq!(emitted[0].element, Some(1));
+        assert_eq!(emitted[0].to_string(), format!("Note in term {} element 1: odd input", squares.implementation.index()));
+    }
+
+    #[test]
+    fn invariant_violation_fails_term() {
+        let mut engine = SimpleEngine::<OpError>::new();
+        engine.set_check_invariants(true);
+
+        let ratio = engine.scalar(|| 1.5, engine.upstream());
+        engine.invariant(&ratio, "ratio within [0, 1]", |r: &f64| (0.0..=1.0).contains(r)).unwrap();
+        let ratio_value = ratio.clone();
+        let percent = engine.scalar(move || *ratio_value * 100.0, engine.upstream().add(&ratio).unwrap());
+
+        for _ in 0..2 {
+            match engine.eval(&percent) {
+                Err(ExpressionError::Failed { term, error }) => match &*error {
+                    ExpressionError::Engine(EngineError::InvariantViolated { invariant, .. }) => {
+                        assert_eq!(term, ratio.implementation.index());
+                        assert_eq!(invariant, "ratio within [0, 1]");
+                    },
+                    _ => panic!("invariant not checked")
+                },
+                _ => panic!("expected the failure of ratio")
+            }
+        }
+        assert_eq!(engine.term_info(&ratio.implementation).unwrap().state, TermState::Failed);
+        assert!(matches!(engine.retry(&ratio.implementation), Err(EngineError::DoubleCalc)));
+
+        let limit = engine.constant(-1);
+        match engine.invariant(&limit, "non-negative limit", |l: &i32| *l >= 0) {
+            Err(EngineError::InvariantViolated { invariant, .. }) => assert_eq!(invariant, "non-negative limit"),
+            _ => panic!("constant not checked")
+        }
+        assert_eq!(engine.term_info(&limit.implementation).unwrap().state, TermState::Failed);
+    }
+
+    #[test]
+    fn once_terms_move_captured_values() {
+        let mut engine = SimpleEngine::<OpError>::new();
+
+        let buffer = vec![1u8; 1024];
+        let base = engine.constant(10);
+        let moved = engine.scalar_once(move || buffer, engine.upstream());
+        let rows = engine.list_once(move || vec![String::from("a")], engine.upstream());
+        let numbers = engine.generator_once(vec![1, 2, 3], engine.upstream());
+
+        assert_eq!(engine.term_info(&base.implementation).unwrap().state, TermState::Evaluated);
+        assert_eq!(*engine.eval(&base).unwrap(), 10);
+        assert_eq!(engine.eval(&moved).unwrap().len(), 1024);
+        assert_eq!(engine.eval(&rows).unwrap()[0], "a");
+        assert_eq!(*engine.eval(&numbers).unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn owned_values_move_out_of_terms() {
+        let mut engine = SimpleEngine::<OpError>::new();
+
+        let output = engine.scalar_once(|| Owned::new(vec![0u8; 1024]), engine.upstream());
+        let size = engine.consume(&output, |buffer| buffer.len()).unwrap();
+        assert!(matches!(engine.consume(&output, |buffer| buffer.len()), Err(EngineError::Consumed)));
+        assert!(matches!(engine.take(&output), Err(ExpressionError::Engine(EngineError::Consumed))));
+        assert!(matches!(engine.upstream().add(&output), Err(EngineError::Consumed)));
+        let late = engine.scalar(|| 0, IndexSet::new().add(&output).unwrap());
+        assert!(matches!(engine.eval(&late), Err(ExpressionError::Engine(EngineError::Consumed))));
+
+        let settings = engine.constant(Owned::new(3));
+        let reader = engine.scalar(|| 4, engine.upstream().add(&settings).unwrap());
+        assert!(matches!(engine.consume(&settings, |s| s), Err(EngineError::Consumed)));
+        assert_eq!(*engine.eval(&reader).unwrap(), 4);
+
+        assert_eq!(*engine.eval(&size).unwrap(), 1024);
+        assert!(output.is_consumed());
+        assert!(matches!(engine.eval(&output), Err(ExpressionError::Engine(EngineError::Consumed))));
+        assert_eq!(engine.term_info(&output.implementation).unwrap().state, TermState::Consumed);
+
+        let report = engine.constant(Owned::new(String::from("done")));
+        assert_eq!(engine.take(&report).unwrap(), "done");
+        assert!(report.with(|r| r.len()).is_err());
+        assert!(matches!(engine.eval(&report), Err(ExpressionError::Engine(EngineError::Consumed))));
+        assert!(engine.eval_keep_going(&report).is_err());
+        assert_eq!(engine.term_info(&report.implementation).unwrap().state, TermState::Consumed);
+        assert!(engine.take(&report).is_err());
+    }
+
+    #[test]
+    fn shared_results_outlive_engine() {
+        let shared = {
+            let mut engine = SimpleEngine::<OpError>::new();
+            let total = engine.list(|| vec![1, 2, 3], engine.upstream());
+            assert!(total.get_arc().is_err());
+            engine.eval_shared(&total).unwrap()
+        };
+
+        let sum = std::thread::spawn(move || shared.iter().sum::<i32>()).join().unwrap();
+        assert_eq!(sum, 6);
+    }
+
+    struct Config {
+        threshold: i32,
+        labels: Vec<String>
+    }
+
+    #[test]
+    fn projection_views_upstream_field() {
+        let mut engine = SimpleEngine::<OpError>::new();
+
+        let config = engine.scalar(|| Config { threshold: 3, labels: vec![String::from("a")] }, engine.upstream());
+        let threshold = engine.project(&config, |c| &c.threshold);
+        let labels = engine.project(&config, |c| &c.labels);
+        let threshold_value = threshold.clone();
+        let doubled = engine.scalar(move || *threshold_value * 2, engine.upstream().add(&threshold).unwrap());
+
+        assert_eq!(*engine.eval(&doubled).unwrap(), 6);
+        assert_eq!(engine.eval(&labels).unwrap().len(), 1);
+        assert!(std::ptr::eq(&*labels, &config.labels));
+        assert_eq!(engine.term_info(&threshold.implementation).unwrap().upstream, vec![config.implementation.clone()]);
+    }
+
+    #[test]
+    fn multi_output_term_runs_once() {
+        let calls = Cell::new(0);
+        let mut engine = SimpleEngine::<OpError>::new();
+
+        let (model, metrics) = engine.scalar2(|| {
+            calls.set(calls.get() + 1);
+            (vec![0.5, 0.25], String::from("loss=0.1"))
+        }, engine.upstream());
+        engine.set_check_invariants(true);
+        engine.invariant(&metrics, "loss reported", |m: &String| m.starts_with("loss")).unwrap();
+        let model_value = model.clone();
+        let weights = engine.scalar(move || model_value.len(), engine.upstream().add(&model).unwrap());
+
+        assert_eq!(*engine.eval(&weights).unwrap(), 2);
+        assert_eq!(engine.term_info(&metrics.implementation).unwrap().state, TermState::Pending);
+        assert_eq!(engine.eval(&metrics).unwrap(), "loss=0.1");
+        assert_eq!(engine.term_info(&metrics.implementation).unwrap().attempts, 1);
+        assert_eq!(calls.get(), 1);
+        assert!(!engine.ancestors(&weights.implementation).unwrap().contains(&metrics.implementation));
+
+        let (a, b, c) = engine.scalar3(|| (1, 'b', "c"), engine.upstream());
+        engine.invariant(&c, "empty", |c: &&str| c.is_empty()).unwrap();
+        assert_eq!((*engine.eval(&a).unwrap(), *engine.eval(&b).unwrap()), (1, 'b'));
+        match engine.eval(&c).unwrap_err().original() {
+            ExpressionError::Engine(EngineError::InvariantViolated { invariant, .. }) => assert_eq!(invariant, "empty"),
+            _ => panic!("invariant of the third output not checked")
+        }
+    }
+}